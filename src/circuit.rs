@@ -0,0 +1,58 @@
+use std::future::Future;
+use std::time::Duration;
+use serde_json::Value;
+use crate::helpers::{get_stale_data, DbWrapper, CACHE_COLLECTIONS};
+use crate::settings::AppSettings;
+use crate::http::HttpError;
+
+pub enum Upstream {
+  Fresh(String),
+  // the last cached entry (marked with `stale: true`), from when upstream was failing
+  Stale(Value)
+}
+
+// runs `fetch` against the innertube `endpoint` unless its circuit is open, falling back to the expired entry for `key` in `collection` when it's open or `fetch` fails
+pub async fn fetch_upstream(endpoint: &str, collection: &str, key: &str, db: &DbWrapper, app_settings: &AppSettings, fetch: impl Future<Output = Result<String, HttpError>>) -> Result<Upstream, HttpError> {
+  if app_settings.state.is_circuit_open(endpoint, app_settings.circuit_breaker_cooldown) {
+    // without a stale entry there's nothing better to do than try anyway
+    if let Some(stale_data) = get_stale_data(collection, key, db, app_settings).await {
+      return Ok(Upstream::Stale(stale_data));
+    }
+  }
+  match fetch.await {
+    Ok(response) => {
+      app_settings.state.record_upstream(endpoint, false, app_settings.circuit_breaker_threshold);
+      Ok(Upstream::Fresh(response))
+    },
+    Err(error) => {
+      app_settings.state.record_upstream(endpoint, true, app_settings.circuit_breaker_threshold);
+      match get_stale_data(collection, key, db, app_settings).await {
+        Some(stale_data) => {
+          log::warn!("Serving stale `{}` for {} ({})", collection, key, error);
+          Ok(Upstream::Stale(stale_data))
+        },
+        None => Err(error)
+      }
+    }
+  }
+}
+
+const STALE_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// expired entries are kept to be served as stale, so they're purged once they're older than `--max-stale-age` instead
+pub fn spawn_stale_purge(app_settings: AppSettings) {
+  if !app_settings.cache_requests {
+    return;
+  }
+  tokio::spawn(async move {
+    loop {
+      let db = app_settings.get_json_db().await;
+      let purged = db.purge_stale(&CACHE_COLLECTIONS, app_settings.cache_timeout, app_settings.max_stale_age).await;
+      if purged > 0 {
+        log::info!("Purged {} stale cache entries", purged);
+      }
+      drop(db);
+      tokio::time::sleep(STALE_PURGE_INTERVAL).await;
+    }
+  });
+}
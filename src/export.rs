@@ -0,0 +1,277 @@
+use serde_json::{json, to_string, to_string_pretty, Value};
+use crate::helpers::DbWrapper;
+use crate::routes::auth::{get_history, get_subscriptions, get_playlists, get_preferences};
+
+// freetube imports subscriptions, playlists, and history from separate files
+pub enum FreeTubeFile {
+  Subscriptions,
+  Playlists,
+  History
+}
+
+impl FreeTubeFile {
+  pub fn from_name(name: &str) -> Option<FreeTubeFile> {
+    match name.to_lowercase().as_str() {
+      "subscriptions" | "profiles" => Some(FreeTubeFile::Subscriptions),
+      "playlists" => Some(FreeTubeFile::Playlists),
+      "history" => Some(FreeTubeFile::History),
+      _ => None
+    }
+  }
+}
+
+// the same formats the importer understands, so exports can always be imported again
+pub enum ExportFormat {
+  Invidious,
+  NewPipe,
+  FreeTube(FreeTubeFile),
+  Opml
+}
+
+impl ExportFormat {
+  // freetube needs to know which of its files to export
+  pub fn from_name(name: &str, file: Option<&str>) -> Option<ExportFormat> {
+    match name.to_lowercase().as_str() {
+      "invidious" => Some(ExportFormat::Invidious),
+      "newpipe" => Some(ExportFormat::NewPipe),
+      "freetube" => file.and_then(FreeTubeFile::from_name).map(ExportFormat::FreeTube),
+      "opml" => Some(ExportFormat::Opml),
+      _ => None
+    }
+  }
+  pub fn file_name(&self) -> &str {
+    match self {
+      ExportFormat::Invidious => "subscription_manager.json",
+      ExportFormat::NewPipe => "newpipe_subscriptions.json",
+      ExportFormat::FreeTube(FreeTubeFile::Subscriptions) => "freetube-subscriptions.db",
+      ExportFormat::FreeTube(FreeTubeFile::Playlists) => "freetube-playlists.db",
+      ExportFormat::FreeTube(FreeTubeFile::History) => "freetube-history.db",
+      ExportFormat::Opml => "subscriptions.opml"
+    }
+  }
+  pub fn content_type(&self) -> &str {
+    match self {
+      ExportFormat::Invidious | ExportFormat::NewPipe => "application/json",
+      ExportFormat::FreeTube(_) => "application/octet-stream",
+      ExportFormat::Opml => "text/x-opml"
+    }
+  }
+}
+
+fn escape_xml(value: &str) -> String {
+  value.replace("&", "&amp;").replace("\"", "&quot;").replace("'", "&apos;").replace("<", "&lt;").replace(">", "&gt;")
+}
+
+// subscriptions, playlists, history, and preferences
+async fn export_invidious(db: &DbWrapper, token: &str) -> Result<String, serde_json::Error> {
+  let subscriptions = get_subscriptions(db, token).await;
+  let playlists = get_playlists(db, token).await;
+  let history = get_history(db, token).await;
+  to_string_pretty(&json!({
+    "subscriptions": subscriptions.into_iter().map(|subscription| subscription.author_id).collect::<Vec::<String>>(),
+    // iv keeps history oldest first
+    "watch_history": history.iter().rev().map(|entry| entry.video_id.clone()).collect::<Vec::<String>>(),
+    // iv only keeps video ids, so the watch times and resume positions go in a field it ignores
+    "yaytapi_watch_history": history,
+    "preferences": get_preferences(db, token).await.unwrap_or(json!({})),
+    "playlists": playlists.into_iter().map(|playlist| json!({
+      "title": playlist.title,
+      "description": playlist.description,
+      "privacy": "Private",
+      "videos": playlist.videos
+    })).collect::<Vec::<Value>>()
+  }))
+}
+
+// newpipe's subscription export only has subscriptions
+async fn export_newpipe(db: &DbWrapper, token: &str) -> Result<String, serde_json::Error> {
+  let subscriptions = get_subscriptions(db, token).await;
+  to_string_pretty(&json!({
+    "app_version": "0.25.2",
+    "app_version_int": 994,
+    "subscriptions": subscriptions.into_iter().map(|subscription| json!({
+      "service_id": 0,
+      "url": format!("https://www.youtube.com/channel/{}", subscription.author_id),
+      "name": subscription.author
+    })).collect::<Vec::<Value>>()
+  }))
+}
+
+async fn export_freetube_subscriptions(db: &DbWrapper, token: &str) -> Result<String, serde_json::Error> {
+  let subscriptions = get_subscriptions(db, token).await;
+  to_string(&json!({
+    "name": "All Channels",
+    "bgColor": "#000000",
+    "textColor": "#FFFFFF",
+    "subscriptions": subscriptions.into_iter().map(|subscription| json!({
+      "id": subscription.author_id,
+      "name": subscription.author,
+      "thumbnail": ""
+    })).collect::<Vec::<Value>>(),
+    "_id": "allChannels"
+  }))
+}
+
+async fn export_freetube_playlists(db: &DbWrapper, token: &str) -> Result<String, serde_json::Error> {
+  let mut lines = vec![];
+  for playlist in get_playlists(db, token).await {
+    lines.push(to_string(&json!({
+      "playlistName": playlist.title,
+      "description": playlist.description,
+      "videos": playlist.videos.into_iter().map(|video_id| json!({
+        "videoId": video_id,
+        "title": "",
+        "author": "",
+        "authorId": "",
+        "lengthSeconds": 0,
+        "timeAdded": playlist.updated * 1000,
+        "type": "video"
+      })).collect::<Vec::<Value>>(),
+      "_id": playlist.playlist_id,
+      "createdAt": playlist.updated * 1000,
+      "lastUpdatedAt": playlist.updated * 1000
+    }))?);
+  }
+  Ok(lines.join("\n"))
+}
+
+async fn export_freetube_history(db: &DbWrapper, token: &str) -> Result<String, serde_json::Error> {
+  let mut lines = vec![];
+  for entry in get_history(db, token).await {
+    lines.push(to_string(&json!({
+      "videoId": entry.video_id,
+      "title": "",
+      "author": "",
+      "authorId": "",
+      "published": 0,
+      "description": "",
+      "viewCount": 0,
+      "lengthSeconds": 0,
+      "watchProgress": entry.position,
+      "timeWatched": entry.watched * 1000,
+      "isLive": false,
+      "type": "video",
+      "_id": entry.video_id
+    }))?);
+  }
+  Ok(lines.join("\n"))
+}
+
+async fn export_opml(db: &DbWrapper, token: &str) -> String {
+  let outlines = get_subscriptions(db, token).await.into_iter().map(|subscription| {
+    format!("      <outline text=\"{}\" title=\"{}\" type=\"rss\" xmlUrl=\"https://www.youtube.com/feeds/videos.xml?channel_id={}\" />", escape_xml(&subscription.author), escape_xml(&subscription.author), escape_xml(&subscription.author_id))
+  }).collect::<Vec::<String>>();
+  format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"1.1\">\n  <body>\n    <outline text=\"YouTube Subscriptions\" title=\"YouTube Subscriptions\">\n{}\n    </outline>\n  </body>\n</opml>\n", outlines.join("\n"))
+}
+
+pub async fn export_user_data(format: &ExportFormat, db: &DbWrapper, token: &str) -> Result<String, serde_json::Error> {
+  match format {
+    ExportFormat::Invidious => export_invidious(db, token).await,
+    ExportFormat::NewPipe => export_newpipe(db, token).await,
+    ExportFormat::FreeTube(FreeTubeFile::Subscriptions) => export_freetube_subscriptions(db, token).await,
+    ExportFormat::FreeTube(FreeTubeFile::Playlists) => export_freetube_playlists(db, token).await,
+    ExportFormat::FreeTube(FreeTubeFile::History) => export_freetube_history(db, token).await,
+    ExportFormat::Opml => Ok(export_opml(db, token).await)
+  }
+}
+
+// these need somewhere to keep the user data
+#[cfg(all(test, feature = "unqlite"))]
+mod tests {
+  use serde_json::json;
+  use crate::helpers::DbWrapper;
+  use crate::settings::AppSettings;
+  use crate::import::{ImportFormat, parse_import, apply_import};
+  use crate::routes::auth::{HistoryEntry, Subscription, UserPlaylist, get_history, set_history, get_subscriptions, set_subscriptions, get_playlists, set_playlists, get_preferences, set_preferences, get_resume_position, set_resume_position};
+  use super::{ExportFormat, export_user_data};
+
+  const CHANNEL_ID: &str = "UC4QobU6STFB0P71PMvOGN5A";
+
+  async fn exported_settings(name: &str) -> AppSettings {
+    let db_name = std::env::temp_dir().join(format!("yaytapi-export-{}.db", name));
+    let _ = std::fs::remove_file(&db_name);
+    let app_settings = AppSettings::from_cli_args(&[String::from("yaytapi"), format!("--db-name={}", db_name.display())]);
+    let db = app_settings.get_json_db().await;
+    set_subscriptions(&db, "source", &[Subscription { author_id: String::from(CHANNEL_ID), author: String::from("jawed") }]).await;
+    set_playlists(&db, "source", &[UserPlaylist {
+      playlist_id: String::from("IVPL1"),
+      title: String::from("Zoo"),
+      description: String::from("animals"),
+      videos: vec![String::from("jNQXAC9IVRw"), String::from("dQw4w9WgXcQ")],
+      updated: 1700000000
+    }]).await;
+    let history = vec![
+      HistoryEntry { video_id: String::from("dQw4w9WgXcQ"), watched: 1700000200, position: 42 },
+      HistoryEntry { video_id: String::from("jNQXAC9IVRw"), watched: 1700000100, position: 7 }
+    ];
+    for entry in &history {
+      set_resume_position(&db, "source", &entry.video_id, entry.position).await;
+    }
+    set_history(&db, "source", &history, app_settings.max_history).await;
+    set_preferences(&db, "source", &json!({ "dark_mode": "dark" })).await;
+    app_settings
+  }
+
+  // exports the source user's data and imports it for `target`
+  async fn round_trip(app_settings: &AppSettings, format: ExportFormat, target: &str) -> DbWrapper {
+    let db = app_settings.get_json_db().await;
+    let export = export_user_data(&format, &db, "source").await.unwrap();
+    let import_format = ImportFormat::detect(format.file_name(), &export).unwrap();
+    let data = parse_import(&import_format, format.file_name(), &export).unwrap();
+    apply_import(app_settings, target, data).await;
+    db
+  }
+
+  fn subscription_ids(subscriptions: Vec<Subscription>) -> Vec<String> {
+    subscriptions.into_iter().map(|subscription| subscription.author_id).collect()
+  }
+
+  async fn assert_history_round_trips(db: &DbWrapper, target: &str) {
+    let history = get_history(db, target).await.into_iter().map(|entry| (entry.video_id, entry.watched, entry.position)).collect::<Vec<_>>();
+    assert_eq!(history, vec![(String::from("dQw4w9WgXcQ"), 1700000200, 42), (String::from("jNQXAC9IVRw"), 1700000100, 7)]);
+    assert_eq!(get_resume_position(db, target, "dQw4w9WgXcQ").await, Some(42));
+  }
+
+  async fn assert_playlists_round_trip(db: &DbWrapper, target: &str) {
+    let playlists = get_playlists(db, target).await;
+    assert_eq!(playlists.len(), 1);
+    assert_eq!(playlists[0].title, "Zoo");
+    assert_eq!(playlists[0].description, "animals");
+    assert_eq!(playlists[0].videos, vec!["jNQXAC9IVRw", "dQw4w9WgXcQ"]);
+  }
+
+  #[actix_web::test]
+  async fn invidious_exports_round_trip() {
+    let app_settings = exported_settings("invidious").await;
+    let db = round_trip(&app_settings, ExportFormat::Invidious, "target").await;
+    assert_eq!(subscription_ids(get_subscriptions(&db, "target").await), vec![CHANNEL_ID]);
+    assert_playlists_round_trip(&db, "target").await;
+    assert_history_round_trips(&db, "target").await;
+    assert_eq!(get_preferences(&db, "target").await, Some(json!({ "dark_mode": "dark" })));
+  }
+
+  #[actix_web::test]
+  async fn freetube_exports_round_trip() {
+    let app_settings = exported_settings("freetube").await;
+    for file in ["subscriptions", "playlists", "history"] {
+      round_trip(&app_settings, ExportFormat::from_name("freetube", Some(file)).unwrap(), "target").await;
+    }
+    let db = app_settings.get_json_db().await;
+    let subscriptions = get_subscriptions(&db, "target").await;
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!((subscriptions[0].author_id.as_str(), subscriptions[0].author.as_str()), (CHANNEL_ID, "jawed"));
+    assert_playlists_round_trip(&db, "target").await;
+    assert_history_round_trips(&db, "target").await;
+  }
+
+  #[actix_web::test]
+  async fn subscription_only_exports_round_trip() {
+    let app_settings = exported_settings("subscriptions").await;
+    for (format, target) in [(ExportFormat::NewPipe, "newpipe"), (ExportFormat::Opml, "opml")] {
+      let db = round_trip(&app_settings, format, target).await;
+      let subscriptions = get_subscriptions(&db, target).await;
+      assert_eq!(subscriptions.len(), 1);
+      assert_eq!((subscriptions[0].author_id.as_str(), subscriptions[0].author.as_str()), (CHANNEL_ID, "jawed"));
+    }
+  }
+}
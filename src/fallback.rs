@@ -0,0 +1,81 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+use chrono::Utc;
+use actix_web::http::Method;
+use serde_json::json;
+use crate::helpers::{get_previous_data, ActixHeadersIntoReqwest, ReqwestHeadersIntoResponseBuilder};
+use crate::http::HttpError;
+use crate::settings::AppSettings;
+
+// only text responses are cached, everything else (ex: thumbnails) is streamed straight through
+fn is_cacheable(content_type: &str) -> bool {
+  content_type.starts_with("application/json") || content_type.starts_with("text/")
+}
+
+// yaytapi's own tokens (see `get_user_token`) are never sent to the fallback instance
+fn has_credentials(req: &HttpRequest) -> bool {
+  req.headers().contains_key("authorization") || req.headers().contains_key("cookie")
+}
+
+// sends the request on to `--fallback-instance` and returns its response, or None when there is no fallback (or it couldn't be reached)
+pub async fn proxy_to_fallback(req: &HttpRequest, app_settings: &AppSettings) -> Option<HttpResponse> {
+  let instance = app_settings.fallback_instance.as_ref()?;
+  // user data only lives on this instance
+  if req.path().starts_with("/api/v1/auth/") {
+    return None;
+  }
+  let path_and_query = req.uri().path_and_query().map(|path_and_query| path_and_query.as_str()).unwrap_or("/");
+  let db = app_settings.get_json_db().await;
+  // the cache is shared by everyone, so requests with credentials never read from it or write to it
+  let cache_fallback = app_settings.cache_fallback && !has_credentials(req);
+  if cache_fallback {
+    if let Some(previous_data) = get_previous_data("fallback", path_and_query, &db, app_settings).await {
+      return Some(HttpResponse::build(StatusCode::from_u16(previous_data["status"].as_u64().unwrap_or(200) as u16).unwrap_or(StatusCode::OK))
+        .content_type(previous_data["content_type"].as_str().unwrap_or("application/json"))
+        .body(String::from(previous_data["body"].as_str().unwrap_or(""))));
+    }
+  }
+  let mut headers = req.get_reqwest_headers();
+  headers.remove("host");
+  headers.remove("connection");
+  headers.remove("authorization");
+  headers.remove("cookie");
+  let forwarded_headers = headers.keys().filter(|header_name| header_name.as_str().starts_with("x-forwarded-")).cloned().collect::<Vec::<_>>();
+  for header_name in forwarded_headers {
+    headers.remove(header_name);
+  }
+  if cache_fallback {
+    // the body is stored as text, so it can't come back compressed
+    headers.remove("accept-encoding");
+  }
+  let http = &app_settings.state.http;
+  let request = http.client.request(req.method().into(), format!("{}{}", instance, path_and_query)).headers(headers);
+  // only requests that don't change anything are retried
+  let res = match if req.method() == Method::GET || req.method() == Method::HEAD { http.send_stream(request).await } else { request.send().await.map_err(HttpError::from) } {
+    Ok(res) => res,
+    Err(error) => {
+      log::warn!("Failed to reach the fallback instance: {}", error);
+      return None;
+    }
+  };
+  let status = res.status();
+  let content_type = String::from(res.headers().get("content-type").and_then(|content_type| content_type.to_str().ok()).unwrap_or(""));
+  if cache_fallback && status.is_success() && is_cacheable(&content_type) {
+    let body = match res.text().await {
+      Ok(body) => body,
+      Err(error) => {
+        log::warn!("Failed to read the fallback instance's response: {}", error);
+        return None;
+      }
+    };
+    db.insert_json("fallback", path_and_query, &json!({
+      "status": status.as_u16(),
+      "content_type": content_type,
+      "body": body,
+      "timestamp": Utc::now().timestamp()
+    })).await;
+    return Some(HttpResponse::build(status).content_type(content_type).body(body));
+  }
+  let client_resp = HttpResponse::build(status);
+  Some(res.headers().add_headers_to_builder(client_resp).streaming(res.bytes_stream()))
+}
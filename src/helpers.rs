@@ -1,19 +1,26 @@
 #[cfg(feature = "unqlite")]
 use unqlite::{UnQLite, KV, Cursor, Direction::Exact};
-use serde_json::{json, from_str,to_string, Value};
+#[cfg(feature = "unqlite")]
+use serde_json::{from_str,to_string};
+use serde_json::{json, Value};
 use serde::{Serialize, Deserialize};
 use mongodb::{Database};
+use mongodb::options::ReplaceOptions;
 use mongodb::bson::{doc};
-use log::{warn,error};
+#[cfg(feature = "unqlite")]
+use log::warn;
+use log::error;
 use chrono::Utc;
 use reqwest::header::{HeaderMap};
 use actix_web::{HttpResponseBuilder, HttpRequest};
 use crate::settings::{AppSettings, DbType};
 
+#[cfg(feature = "unqlite")]
 pub trait JsonDb {
   fn seek_for_json(&self, key: &str) -> Option<Value>;
   fn insert_json(&self, key: &str, value: &Value);
   fn delete(&self, key: &str);
+  fn purge(&self, key_prefixes: &[String], should_purge: &dyn Fn(&CacheExpiry) -> bool) -> u64;
 }
 #[cfg(feature = "unqlite")]
 impl JsonDb for UnQLite {
@@ -51,6 +58,39 @@ impl JsonDb for UnQLite {
       }
     };
   }
+  // walks every key, since unqlite has no way to look entries up by their value (only the expiry fields are parsed out of each one)
+  fn purge(&self, key_prefixes: &[String], should_purge: &dyn Fn(&CacheExpiry) -> bool) -> u64 {
+    let mut purged = 0;
+    let mut entry = self.first();
+    while let Some(current) = entry {
+      let key = current.key();
+      let is_match = key_prefixes.iter().any(|prefix| key.starts_with(prefix.as_bytes())) && match serde_json::from_slice::<CacheExpiry>(&current.value()) {
+        Ok(expiry) => should_purge(&expiry),
+        Err(_) => false
+      };
+      entry = if is_match {
+        purged += 1;
+        current.delete()
+      } else {
+        current.next()
+      };
+    }
+    purged
+  }
+}
+
+// every collection innertube (and fallback) responses are cached in, which are kept in their own unqlite file so purging them doesn't walk the user data
+pub const CACHE_COLLECTIONS: [&str; 8] = ["player", "next", "playlist", "channel", "search", "shorts-sequence", "suggestions", "fallback"];
+
+#[cfg(feature = "unqlite")]
+// marks that the cache entries from before `CACHE_COLLECTIONS` got their own file were cleared out of the main one
+const CACHE_MOVED_KEY: &str = "cache-moved";
+
+// the only fields of a cache entry needed to tell when it expires
+#[derive(Deserialize)]
+pub struct CacheExpiry {
+  timestamp: Option<i64>,
+  expires: Option<i64>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -63,48 +103,61 @@ pub struct DbWrapper {
   pub mongodb: Option<Database>,
   #[cfg(feature = "unqlite")]
   pub unqlite: Option<UnQLite>,
-  #[cfg(not(feature = "unqlite"))]
-  pub unqlite: Option<String>,
+  #[cfg(feature = "unqlite")]
+  pub unqlite_cache: Option<UnQLite>,
   pub preference: DbType
 }
 
 // todo✏ figure out better solution for concurency
 impl DbWrapper {
   pub fn none() -> DbWrapper {
-    DbWrapper { mongodb: None, unqlite: None, preference: DbType::None }
+    DbWrapper {
+      mongodb: None,
+      #[cfg(feature = "unqlite")]
+      unqlite: None,
+      #[cfg(feature = "unqlite")]
+      unqlite_cache: None,
+      preference: DbType::None
+    }
   }
   #[cfg(feature = "unqlite")]
-  pub fn unqlite(unqlite: UnQLite) -> DbWrapper {
+  pub fn unqlite(unqlite: UnQLite, unqlite_cache: UnQLite) -> DbWrapper {
     DbWrapper {
       mongodb: None,
       unqlite: Some(unqlite),
+      unqlite_cache: Some(unqlite_cache),
       preference: DbType::UnQLite
     }
   }
   pub fn mongodb(mongodb: Database) -> DbWrapper {
     DbWrapper {
       mongodb: Some(mongodb),
+      #[cfg(feature = "unqlite")]
       unqlite: None,
+      #[cfg(feature = "unqlite")]
+      unqlite_cache: None,
       preference: DbType::MongoDb
     }
   }
+  #[cfg(feature = "unqlite")]
+  fn unqlite_for(&self, collection_name: &str) -> &Option<UnQLite> {
+    if CACHE_COLLECTIONS.contains(&collection_name) { &self.unqlite_cache } else { &self.unqlite }
+  }
   // TODO ✏ add better error handling for timeouts per https://docs.rs/mongodb/latest/mongodb/#warning-about-timeouts--cancellation
   pub async fn seek_for_json(&self, collection_name: &str, key: &str) -> Option<Value> {
     match self.preference {
       DbType::UnQLite => {
-        let Some(db) = &self.unqlite else { todo!() };
         #[cfg(feature = "unqlite")]
-        return db.seek_for_json(&format!("{}-{}", collection_name, key));
+        if let Some(db) = self.unqlite_for(collection_name) {
+          return db.seek_for_json(&format!("{}-{}", collection_name, key));
+        }
         None
       },
       DbType::MongoDb => {
         let Some(db) = &self.mongodb else { todo!() };
         let collection = db.collection::<Value>(&format!("yayti.{}", collection_name));
         match collection.find_one(doc! { "key": key }, None).await {
-          Ok(found) => match found {
-            Some(found) => Some(json!(found["value"])),
-            None => None
-          },
+          Ok(found) => found.map(|found| json!(found["value"])),
           Err(_) => None
         }
       },
@@ -114,14 +167,16 @@ impl DbWrapper {
   pub async fn insert_json(&self, collection_name: &str, key: &str, value: &Value) {
     match self.preference {
       DbType::UnQLite => {
-        let Some(db) = &self.unqlite else { todo!() };
         #[cfg(feature = "unqlite")]
-        db.insert_json(&format!("{}-{}", collection_name, key), value);
+        if let Some(db) = self.unqlite_for(collection_name) {
+          db.insert_json(&format!("{}-{}", collection_name, key), value);
+        }
       },
       DbType::MongoDb => {
         let Some(db) = &self.mongodb else { todo!() };
         let collection = db.collection::<Value>(&format!("yayti.{}", collection_name));
-        match collection.insert_one(json!(JsonKVPair { key: String::from(key), value: value.clone() }), None).await {
+        // expired entries aren't deleted anymore, so this replaces them instead of adding another one with the same key
+        match collection.replace_one(doc! { "key": key }, json!(JsonKVPair { key: String::from(key), value: value.clone() }), ReplaceOptions::builder().upsert(true).build()).await {
           Ok(_) => {},
           Err(error) => error!("❌ insert_json failed: {}", error)
         }
@@ -132,9 +187,10 @@ impl DbWrapper {
   pub async fn delete(&self, collection_name: &str, key: &str) {
     match self.preference {
       DbType::UnQLite => {
-        let Some(db) = &self.unqlite else { todo!() };
         #[cfg(feature = "unqlite")]
-        db.delete(&format!("{}-{}", collection_name, key))
+        if let Some(db) = self.unqlite_for(collection_name) {
+          db.delete(&format!("{}-{}", collection_name, key))
+        }
       },
       DbType::MongoDb => {
         let Some(db) = &self.mongodb else { todo!() };
@@ -147,33 +203,93 @@ impl DbWrapper {
       DbType::None => {}
     }
   }
+  // deletes the cache entries in `collection_names` that expired more than `max_stale_age` seconds ago, and returns how many there were
+  pub async fn purge_stale(&self, collection_names: &[&str], cache_timeout: u64, max_stale_age: u64) -> u64 {
+    let now = Utc::now().timestamp();
+    match self.preference {
+      DbType::UnQLite => self.purge_stale_unqlite(collection_names, now, cache_timeout, max_stale_age),
+      DbType::MongoDb => {
+        let Some(db) = &self.mongodb else { todo!() };
+        // the same as `is_past_max_stale_age`
+        let oldest_expiry = now - max_stale_age as i64;
+        let mut purged = 0;
+        for collection_name in collection_names {
+          let collection = db.collection::<Value>(&format!("yayti.{}", collection_name));
+          match collection.delete_many(doc! { "$or": [
+            { "value.expires": { "$exists": false }, "value.timestamp": { "$lt": oldest_expiry - cache_timeout as i64 } },
+            { "value.expires": { "$lt": oldest_expiry } }
+          ] }, None).await {
+            Ok(result) => purged += result.deleted_count,
+            Err(error) => error!("❌ purge_stale failed: {}", error)
+          }
+        }
+        purged
+      },
+      DbType::None => 0
+    }
+  }
+  // one walk over the cache file for every collection
+  #[cfg(feature = "unqlite")]
+  fn purge_stale_unqlite(&self, collection_names: &[&str], now: i64, cache_timeout: u64, max_stale_age: u64) -> u64 {
+    let Some(cache_db) = &self.unqlite_cache else { return 0 };
+    let key_prefixes = collection_names.iter().map(|collection_name| format!("{}-", collection_name)).collect::<Vec::<String>>();
+    let mut purged = cache_db.purge(&key_prefixes, &|expiry| is_past_max_stale_age(expiry, now, cache_timeout, max_stale_age));
+    // the cache used to share the main file, whatever is left of it there is dropped once
+    if let Some(db) = &self.unqlite {
+      if db.seek_for_json(CACHE_MOVED_KEY).is_none() {
+        purged += db.purge(&key_prefixes, &|_| true);
+        db.insert_json(CACHE_MOVED_KEY, &json!(true));
+      }
+    }
+    purged
+  }
+  #[cfg(not(feature = "unqlite"))]
+  fn purge_stale_unqlite(&self, _collection_names: &[&str], _now: i64, _cache_timeout: u64, _max_stale_age: u64) -> u64 {
+    0
+  }
 }
 
+// entries without a `timestamp` (ex: user data) aren't cache entries, so they never expire
+fn is_past_max_stale_age(expiry: &CacheExpiry, now: i64, cache_timeout: u64, max_stale_age: u64) -> bool {
+  let expiry = match (expiry.expires, expiry.timestamp) {
+    (Some(expires), _) => expires,
+    (None, Some(timestamp)) => timestamp + cache_timeout as i64,
+    (None, None) => return false
+  };
+  now - expiry > max_stale_age as i64
+}
+
+// entries with `expires` (ex: upcoming premieres) are kept until then instead of for `cache_timeout`, and ones without a `timestamp` never expire
+pub fn is_expired(json: &Value, now: i64, cache_timeout: u64) -> bool {
+  let Some(timestamp) = json["timestamp"].as_i64() else { return false };
+  match json["expires"].as_i64() {
+    Some(expires) => now >= expires,
+    None => (now - timestamp) as u64 > cache_timeout
+  }
+}
 
 pub async fn get_previous_data(collection: &str, key: &str, db: &DbWrapper, app_settings: &AppSettings) -> Option<Value> {
   if app_settings.cache_requests {
-    match db.seek_for_json(collection, key).await {
-      Some(json) => {
-        match json["timestamp"].as_i64() {
-          Some(timestamp) => {
-            let current_timestamp = Utc::now().timestamp();
-            let offset = current_timestamp - timestamp;
-            if offset as u64 > app_settings.cache_timeout {
-              db.delete(collection, key).await;
-              None
-            } else {
-              Some(json)
-            }
-          },
-          None => Some(json)
-        }
-      },
-      None => None
-    }
+    // expired entries are kept around in case upstream goes down (see `get_stale_data`)
+    db.seek_for_json(collection, key).await.filter(|json| !is_expired(json, Utc::now().timestamp(), app_settings.cache_timeout))
   } else {
     None
   }
  }
+// the cached entry whether it's expired or not (up to `--max-stale-age`), marked with `stale: true`
+pub async fn get_stale_data(collection: &str, key: &str, db: &DbWrapper, app_settings: &AppSettings) -> Option<Value> {
+  if !app_settings.cache_requests {
+    return None;
+  }
+  let mut json = db.seek_for_json(collection, key).await?;
+  let expiry = CacheExpiry { timestamp: json["timestamp"].as_i64(), expires: json["expires"].as_i64() };
+  if is_past_max_stale_age(&expiry, Utc::now().timestamp(), app_settings.cache_timeout, app_settings.max_stale_age) {
+    return None;
+  }
+  json["stale"] = json!(true);
+  Some(json)
+}
+
 pub trait ActixHeadersIntoReqwest {
   fn get_reqwest_headers(&self) -> HeaderMap;
 }
@@ -196,7 +312,13 @@ impl ReqwestHeadersIntoResponseBuilder for HeaderMap {
   fn add_headers_to_builder(&self, mut builder: HttpResponseBuilder) -> HttpResponseBuilder {
     for (header_name, header_value) in self {
       if header_name != "content-length" {
-        builder.insert_header((header_name, header_value.to_str().unwrap()));
+        match header_value.to_str() {
+          Ok(header_value) => {
+            builder.insert_header((header_name, header_value));
+          },
+          // ex: non-ascii values, which aren't worth failing the whole response over
+          Err(_) => log::warn!("Skipping the `{}` header, its value isn't valid ascii", header_name)
+        }
       }
     }
     builder
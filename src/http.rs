@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use actix_web::web::Bytes;
+use futures_util::{Stream, StreamExt};
+use reqwest::{Body, Client, ClientBuilder, Proxy, RequestBuilder, Response, ResponseBuilderExt, StatusCode};
+use serde::{Deserialize, Serialize};
+use urlencoding::encode;
+use crate::recording::{Recording, describe_request, record, replay};
+use crate::sessions::SessionPool;
+
+// a random number without pulling in `rand` (every `RandomState` is seeded differently)
+pub fn random_u64() -> u64 {
+  RandomState::new().build_hasher().finish()
+}
+
+// the longest a retry waits, however many `--retries` there are
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/112.0.0.0 Safari/537.36";
+
+#[derive(Debug)]
+pub enum HttpError {
+  // nothing was received for `--read-timeout` before the response started
+  Timeout(Duration),
+  Reqwest(reqwest::Error)
+}
+
+impl HttpError {
+  pub fn is_timeout(&self) -> bool {
+    match self {
+      HttpError::Timeout(_) => true,
+      HttpError::Reqwest(error) => error.is_timeout()
+    }
+  }
+  pub fn is_connect(&self) -> bool {
+    match self {
+      HttpError::Timeout(_) => false,
+      HttpError::Reqwest(error) => error.is_connect()
+    }
+  }
+}
+
+impl Display for HttpError {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    match self {
+      HttpError::Timeout(read_timeout) => write!(f, "Nothing was received for {} seconds", read_timeout.as_secs()),
+      HttpError::Reqwest(error) => write!(f, "{}", error)
+    }
+  }
+}
+
+impl Error for HttpError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      HttpError::Timeout(_) => None,
+      HttpError::Reqwest(error) => Some(error)
+    }
+  }
+}
+
+impl From<reqwest::Error> for HttpError {
+  fn from(error: reqwest::Error) -> HttpError {
+    HttpError::Reqwest(error)
+  }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub enum ProxyRotation {
+  RoundRobin,
+  Random
+}
+
+// how an upstream request goes out: through which `--proxy` (by index, so credentials in proxy urls never end up in responses) and from which source address
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Egress {
+  pub proxy: Option<usize>,
+  pub source_address: Option<IpAddr>
+}
+
+impl Egress {
+  // appended to locally proxied stream urls, since googlevideo only serves streams to the ip that fetched the player response
+  pub fn query_params(&self) -> String {
+    let mut query_params = String::new();
+    if let Some(proxy) = self.proxy {
+      query_params.push_str(&format!("&egress={}", proxy));
+    }
+    if let Some(source_address) = self.source_address {
+      query_params.push_str(&format!("&source={}", encode(&source_address.to_string())));
+    }
+    query_params
+  }
+}
+
+// ex: `2001:db8:1234:5678::/64`
+fn parse_ipv6_prefix(prefix: &str) -> Option<(u128, u32)> {
+  let (address, length) = prefix.split_once('/')?;
+  let address = Ipv6Addr::from_str(address).ok()?;
+  let length = u32::from_str(length).ok().filter(|length| *length <= 128)?;
+  Some((u128::from(address), length))
+}
+
+// the clients built for each egress are kept around (so their connections are pooled), up to this many (then the least recently used one is dropped)
+const MAX_EGRESS_CLIENTS: usize = 1024;
+
+// the reqwest clients every upstream request goes through, so connections are pooled between requests and workers
+pub struct HttpClient {
+  // connects directly (used for everything that doesn't have to come from the same ip as innertube, ex: thumbnails)
+  pub client: Client,
+  proxies: Vec<Proxy>,
+  source_addresses: Vec<IpAddr>,
+  // the network bits and the prefix length
+  ipv6_prefix: Option<(u128, u32)>,
+  rotation: ProxyRotation,
+  next_proxy: AtomicUsize,
+  next_source_address: AtomicUsize,
+  // reqwest proxies and source addresses are set per client, which are kept with when they were last used
+  egress_clients: Mutex<HashMap<Egress, (Client, u64)>>,
+  egress_clients_used: AtomicU64,
+  max_egress_clients: usize,
+  pool_size: usize,
+  connect_timeout: Duration,
+  user_agent: String,
+  read_timeout: Duration,
+  retries: u32,
+  retry_delay: Duration,
+  recording: Recording,
+  // where innertube, `iframe_api` and player.js are fetched from (ex: a local mock of youtube)
+  pub innertube_url: String,
+  // the visitor data and cookies innertube requests are sent with (filled by `sessions::spawn_session_keeper`)
+  pub sessions: SessionPool
+}
+
+impl HttpClient {
+  pub fn new(pool_size: usize, connect_timeout: u64, read_timeout: u64, user_agent: &str, retries: u32, retry_delay: u64) -> HttpClient {
+    let connect_timeout = Duration::from_secs(connect_timeout);
+    HttpClient {
+      client: client_builder(pool_size, connect_timeout, user_agent).build().unwrap_or_default(),
+      proxies: vec![],
+      source_addresses: vec![],
+      ipv6_prefix: None,
+      rotation: ProxyRotation::RoundRobin,
+      next_proxy: AtomicUsize::new(0),
+      next_source_address: AtomicUsize::new(0),
+      egress_clients: Mutex::new(HashMap::new()),
+      egress_clients_used: AtomicU64::new(0),
+      max_egress_clients: MAX_EGRESS_CLIENTS,
+      pool_size,
+      connect_timeout,
+      user_agent: String::from(user_agent),
+      read_timeout: Duration::from_secs(read_timeout),
+      retries,
+      retry_delay: Duration::from_millis(retry_delay),
+      recording: Recording::Off,
+      innertube_url: String::from("https://www.youtube.com"),
+      sessions: SessionPool::default()
+    }
+  }
+  pub fn with_innertube_url(mut self, innertube_url: &str) -> HttpClient {
+    self.innertube_url = String::from(innertube_url.trim_end_matches('/'));
+    self
+  }
+  pub fn with_recording(mut self, recording: Recording) -> HttpClient {
+    self.recording = recording;
+    self
+  }
+  // `http://`, `https://` and `socks5://` proxies (invalid ones are skipped)
+  pub fn with_proxies(mut self, proxies: &[String], rotation: ProxyRotation) -> HttpClient {
+    self.proxies = proxies.iter().filter_map(|proxy| {
+      match Proxy::all(proxy) {
+        Ok(proxy) => Some(proxy),
+        Err(error) => {
+          log::warn!("Invalid proxy ({}), it won't be used", error);
+          None
+        }
+      }
+    }).collect();
+    self.rotation = rotation;
+    self
+  }
+  // local addresses to send requests from, either a list of them or every address in an ipv6 prefix (the list wins when both are given)
+  pub fn with_source_addresses(mut self, source_addresses: &[IpAddr], ipv6_prefix: Option<&str>) -> HttpClient {
+    self.source_addresses = source_addresses.to_vec();
+    self.ipv6_prefix = ipv6_prefix.and_then(|ipv6_prefix| {
+      let parsed_prefix = parse_ipv6_prefix(ipv6_prefix);
+      if parsed_prefix.is_none() {
+        log::warn!("Invalid ipv6 prefix: {}", ipv6_prefix);
+      }
+      parsed_prefix
+    });
+    self
+  }
+  fn pick_index(&self, counter: &AtomicUsize, len: usize) -> usize {
+    match self.rotation {
+      ProxyRotation::RoundRobin => counter.fetch_add(1, Ordering::Relaxed) % len,
+      ProxyRotation::Random => (random_u64() % len as u64) as usize
+    }
+  }
+  fn pick_source_address(&self) -> Option<IpAddr> {
+    if !self.source_addresses.is_empty() {
+      return Some(self.source_addresses[self.pick_index(&self.next_source_address, self.source_addresses.len())]);
+    }
+    let (network, length) = self.ipv6_prefix?;
+    let host_mask = u128::MAX.checked_shr(length).unwrap_or(0);
+    let host = match self.rotation {
+      ProxyRotation::RoundRobin => self.next_source_address.fetch_add(1, Ordering::Relaxed) as u128,
+      ProxyRotation::Random => ((random_u64() as u128) << 64) | random_u64() as u128
+    } & host_mask;
+    // the all zeros host is the subnet's anycast address
+    let host = if host == 0 && host_mask != 0 { 1 } else { host };
+    Some(IpAddr::V6(Ipv6Addr::from((network & !host_mask) | host)))
+  }
+  // how the next upstream request to innertube or googlevideo should go out
+  pub fn pick_egress(&self) -> Egress {
+    Egress {
+      proxy: if self.proxies.is_empty() { None } else { Some(self.pick_index(&self.next_proxy, self.proxies.len())) },
+      source_address: self.pick_source_address()
+    }
+  }
+  fn is_source_address_allowed(&self, source_address: &IpAddr) -> bool {
+    if self.source_addresses.contains(source_address) {
+      return true;
+    }
+    match (self.ipv6_prefix, source_address) {
+      (Some((network, length)), IpAddr::V6(source_address)) => {
+        let network_mask = !u128::MAX.checked_shr(length).unwrap_or(0);
+        u128::from(*source_address) & network_mask == network & network_mask
+      },
+      _ => false
+    }
+  }
+  pub fn is_egress_configured(&self, egress: &Egress) -> bool {
+    egress.proxy.is_none_or(|proxy| proxy < self.proxies.len()) && egress.source_address.is_none_or(|source_address| self.is_source_address_allowed(&source_address))
+  }
+  // the egress from `Egress::query_params` (anything that isn't configured anymore, ex: after a restart, or never was goes out directly)
+  pub fn egress_from_query(&self, proxy: Option<usize>, source_address: Option<&str>) -> Egress {
+    Egress {
+      proxy: proxy.filter(|proxy| *proxy < self.proxies.len()),
+      source_address: source_address.and_then(|source_address| IpAddr::from_str(source_address).ok()).filter(|source_address| self.is_source_address_allowed(source_address))
+    }
+  }
+  pub fn egress_client(&self, egress: &Egress) -> Client {
+    if egress == &Egress::default() {
+      return self.client.clone();
+    }
+    let used = self.egress_clients_used.fetch_add(1, Ordering::Relaxed);
+    let mut egress_clients = self.egress_clients.lock().unwrap();
+    if let Some((client, last_used)) = egress_clients.get_mut(egress) {
+      *last_used = used;
+      return client.clone();
+    }
+    let mut builder = client_builder(self.pool_size, self.connect_timeout, &self.user_agent).local_address(egress.source_address);
+    if let Some(proxy) = egress.proxy.and_then(|proxy| self.proxies.get(proxy)) {
+      builder = builder.proxy(proxy.clone());
+    }
+    match builder.build() {
+      Ok(client) => {
+        // rotating through an ipv6 prefix would otherwise keep a client for every address ever used
+        // (only the least recently used one is dropped, so the ones in use keep their pooled connections)
+        if egress_clients.len() >= self.max_egress_clients {
+          if let Some(least_recently_used) = egress_clients.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(egress, _)| *egress) {
+            egress_clients.remove(&least_recently_used);
+          }
+        }
+        egress_clients.insert(*egress, (client.clone(), used));
+        client
+      },
+      Err(error) => {
+        log::warn!("Failed to build a client for an egress, connecting directly instead: {}", error);
+        self.client.clone()
+      }
+    }
+  }
+  // for idempotent requests whose responses are read in full (innertube, player.js, thumbnails, etc), which are the ones that get recorded and replayed
+  pub async fn send(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+    match (&self.recording, describe_request(&request)) {
+      (Recording::Replay(directory), Some((name, _))) => Ok(replay(directory, &name)),
+      (Recording::Record(directory), Some((name, request_description))) => {
+        let response = self.send_with_retries(request).await?;
+        Ok(record(directory, &name, request_description, response).await?)
+      },
+      _ => self.send_with_retries(request).await
+    }
+  }
+  // for idempotent requests whose responses are streamed to the client (ex: `/videoplayback`), which are never recorded
+  pub async fn send_stream(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+    self.send_with_retries(request).await
+  }
+  // exponential backoff (up to `MAX_RETRY_DELAY`), with up to 50% jitter either way so retries from every worker don't line up
+  fn backoff(&self, attempt: u32) -> Duration {
+    let retry_delay = u64::try_from(self.retry_delay.as_millis()).unwrap_or(u64::MAX);
+    let delay = retry_delay.saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1))).min(MAX_RETRY_DELAY.as_millis() as u64);
+    let jitter = if delay > 0 { random_u64() % delay } else { 0 };
+    Duration::from_millis(delay / 2 + jitter)
+  }
+  async fn send_with_retries(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+    let mut attempt = 0;
+    loop {
+      // requests with streaming bodies can't be cloned, so they only get the one try
+      let Some(try_request) = request.try_clone() else {
+        return self.send_with_read_timeout(request).await;
+      };
+      match self.send_with_read_timeout(try_request).await {
+        Ok(response) if attempt < self.retries && is_retryable_status(response.status()) => {
+          log::info!("Retrying {} after {}", response.url(), response.status());
+        },
+        Err(error) if attempt < self.retries && (error.is_connect() || error.is_timeout()) => {
+          log::info!("Retrying after {}", error);
+        },
+        result => return result
+      }
+      attempt += 1;
+      tokio::time::sleep(self.backoff(attempt)).await;
+    }
+  }
+  // `--read-timeout` is how long to wait for the response to start and then for each chunk of its body (not for all of it, since streams can be long)
+  async fn send_with_read_timeout(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+    match tokio::time::timeout(self.read_timeout, request.send()).await {
+      Ok(response) => Ok(with_idle_timeout(response?, self.read_timeout)),
+      Err(_) => Err(HttpError::Timeout(self.read_timeout))
+    }
+  }
+}
+
+fn timed_out(read_timeout: Duration) -> Box<dyn Error + Send + Sync> {
+  Box::new(io::Error::new(io::ErrorKind::TimedOut, format!("Nothing was received for {} seconds", read_timeout.as_secs())))
+}
+
+// ends with a timeout error once the next chunk takes longer than `read_timeout`
+fn idle_timeout(stream: impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static, read_timeout: Duration) -> impl Stream<Item = Result<Bytes, Box<dyn Error + Send + Sync>>> {
+  futures_util::stream::unfold(Some(Box::pin(stream)), move |stream| async move {
+    let mut stream = stream?;
+    match tokio::time::timeout(read_timeout, stream.next()).await {
+      Ok(Some(Ok(chunk))) => Some((Ok(chunk), Some(stream))),
+      Ok(Some(Err(error))) => Some((Err(Box::new(error) as Box<dyn Error + Send + Sync>), None)),
+      Ok(None) => None,
+      Err(_) => Some((Err(timed_out(read_timeout)), None))
+    }
+  })
+}
+
+// the same response, with its body read through `idle_timeout` (reqwest only has a timeout for the whole response)
+fn with_idle_timeout(response: Response, read_timeout: Duration) -> Response {
+  let mut builder = ::http::Response::builder().status(response.status()).version(response.version()).url(response.url().clone());
+  // already decompressed (reqwest drops `content-encoding` when it decompresses), so these are what the body will be
+  for (name, value) in response.headers() {
+    builder = builder.header(name, value);
+  }
+  // everything came from a valid response, so this only fails if `http` gets stricter, and then the body is just read without the timeout
+  let Ok(timed_response) = builder.body(()) else { return response };
+  Response::from(timed_response.map(|_| Body::wrap_stream(idle_timeout(response.bytes_stream(), read_timeout))))
+}
+
+fn client_builder(pool_size: usize, connect_timeout: Duration, user_agent: &str) -> ClientBuilder {
+  Client::builder()
+    .pool_max_idle_per_host(pool_size)
+    .connect_timeout(connect_timeout)
+    // innertube requests set the user agent of the client they're pretending to be, this is for everything else (thumbnails, streams, etc)
+    .user_agent(user_agent)
+}
+
+impl Default for HttpClient {
+  fn default() -> HttpClient {
+    HttpClient::new(32, 5, 30, DEFAULT_USER_AGENT, 2, 250)
+  }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+  status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Read, Write};
+  use std::net::{IpAddr, Ipv6Addr, TcpListener};
+  use std::time::Duration;
+  use super::{Egress, HttpClient, HttpError, DEFAULT_USER_AGENT, MAX_RETRY_DELAY};
+
+  // answers one request by writing each chunk after its delay (the connection stays open until the last one is written)
+  fn serve(chunks: Vec<(u64, &'static str)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let _ = stream.read(&mut [0; 1024]);
+      for (delay, chunk) in chunks {
+        std::thread::sleep(Duration::from_millis(delay));
+        let _ = stream.write_all(chunk.as_bytes());
+      }
+    });
+    url
+  }
+
+  // a 1 second read timeout and no retries
+  fn http() -> HttpClient {
+    HttpClient::new(1, 1, 1, DEFAULT_USER_AGENT, 0, 0)
+  }
+
+  const HEADERS: &str = "HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n";
+
+  #[actix_web::test]
+  async fn times_out_when_the_response_doesnt_start() {
+    let url = serve(vec![(3000, HEADERS)]);
+    let http = http();
+    let Err(error) = http.send(http.client.get(url)).await else { panic!("didn't time out") };
+    assert!(matches!(error, HttpError::Timeout(_)));
+    assert!(error.is_timeout());
+  }
+
+  #[actix_web::test]
+  async fn times_out_when_the_body_stalls() {
+    let url = serve(vec![(0, HEADERS), (0, "abc"), (3000, "defghi")]);
+    let http = http();
+    let response = http.send_stream(http.client.get(url)).await.unwrap();
+    let Err(error) = response.bytes().await else { panic!("didn't time out") };
+    assert!(error.is_timeout());
+  }
+
+  #[actix_web::test]
+  async fn keeps_reading_a_slow_body_that_keeps_arriving() {
+    // 1.8 seconds in total, but never more than 0.6 without a chunk
+    let url = serve(vec![(0, HEADERS), (600, "abc"), (600, "def"), (600, "ghi")]);
+    let http = http();
+    let response = http.send(http.client.get(url)).await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "abcdefghi");
+  }
+
+  #[test]
+  fn caps_the_backoff_without_overflowing() {
+    let http = HttpClient::new(1, 1, 1, DEFAULT_USER_AGENT, 100, 250);
+    // 250ms with 50% jitter either way
+    assert!((125..375).contains(&http.backoff(1).as_millis()));
+    assert!((250..750).contains(&http.backoff(2).as_millis()));
+    for attempt in [20, 64, 65, u32::MAX] {
+      assert!(http.backoff(attempt) < MAX_RETRY_DELAY * 3 / 2);
+    }
+  }
+
+  fn egress(i: u128) -> Egress {
+    Egress { proxy: None, source_address: Some(IpAddr::V6(Ipv6Addr::from(0x20010db8_u128 << 96 | i))) }
+  }
+
+  #[test]
+  fn evicts_only_the_least_recently_used_egress_client() {
+    let http = HttpClient { max_egress_clients: 3, ..HttpClient::default() };
+    for i in 0..3 {
+      http.egress_client(&egress(i));
+    }
+    // the first one is still in use, so the second one is the oldest
+    http.egress_client(&egress(0));
+    http.egress_client(&egress(3));
+    let egress_clients = http.egress_clients.lock().unwrap();
+    assert_eq!(egress_clients.len(), 3);
+    assert!(egress_clients.contains_key(&egress(0)));
+    assert!(!egress_clients.contains_key(&egress(1)));
+    assert!(egress_clients.contains_key(&egress(2)));
+    assert!(egress_clients.contains_key(&egress(3)));
+  }
+}
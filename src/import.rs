@@ -0,0 +1,437 @@
+use std::sync::OnceLock;
+use serde::Serialize;
+use serde_json::{from_str, from_value, Value};
+use regex::Regex;
+use chrono::Utc;
+use crate::local::{video_link_or_id_to_id, channel_link_or_id_to_id};
+use crate::routes::auth::{HistoryEntry, Subscription, UserPlaylist, lock_user_data, get_history, set_history, set_resume_position, get_subscriptions, set_subscriptions, get_playlists, set_playlists, set_preferences};
+use crate::settings::AppSettings;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImportFormat {
+  // any podcast-style subscription list (yt, newpipe, and most readers can export this)
+  Opml,
+  // newpipe's `subscriptions.json`
+  NewPipe,
+  // freetube's `.db` files (newline delimited json for profiles, playlists, and history)
+  FreeTube,
+  // invidious's `subscription_manager` json export
+  Invidious,
+  // google takeout's `subscriptions.csv` or one of the playlist csvs
+  Takeout
+}
+
+impl ImportFormat {
+  pub fn from_name(name: &str) -> Option<ImportFormat> {
+    match name.to_lowercase().as_str() {
+      "opml" => Some(ImportFormat::Opml),
+      "newpipe" => Some(ImportFormat::NewPipe),
+      "freetube" => Some(ImportFormat::FreeTube),
+      "invidious" => Some(ImportFormat::Invidious),
+      "takeout" | "csv" => Some(ImportFormat::Takeout),
+      _ => None
+    }
+  }
+  // guesses the format from the file name and contents
+  pub fn detect(file_name: &str, contents: &str) -> Option<ImportFormat> {
+    let trimmed = contents.trim_start_matches('\u{feff}').trim_start();
+    if file_name.ends_with(".opml") || file_name.ends_with(".xml") || trimmed.starts_with("<") {
+      return Some(ImportFormat::Opml);
+    }
+    if file_name.ends_with(".db") {
+      return Some(ImportFormat::FreeTube);
+    }
+    if file_name.ends_with(".csv") {
+      return Some(ImportFormat::Takeout);
+    }
+    match from_str::<Value>(trimmed) {
+      Ok(json) => {
+        if json.get("_id").is_some() || json.get("playlistName").is_some() || json.get("videoId").is_some() {
+          // a freetube `.db` file with a single entry in it
+          Some(ImportFormat::FreeTube)
+        } else if json["subscriptions"].as_array().map(|subscriptions| subscriptions.iter().any(|subscription| subscription.get("url").is_some())).unwrap_or(false) {
+          Some(ImportFormat::NewPipe)
+        } else if json.is_object() {
+          Some(ImportFormat::Invidious)
+        } else {
+          None
+        }
+      },
+      Err(_) => {
+        if trimmed.lines().all(|line| line.trim().is_empty() || from_str::<Value>(line).is_ok()) {
+          Some(ImportFormat::FreeTube)
+        } else if trimmed.contains(",") {
+          Some(ImportFormat::Takeout)
+        } else {
+          None
+        }
+      }
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct ImportedData {
+  pub subscriptions: Vec<Subscription>,
+  // playlist ids are assigned when the import is applied
+  pub playlists: Vec<UserPlaylist>,
+  // newest first
+  pub history: Vec<HistoryEntry>,
+  pub preferences: Option<Value>
+}
+
+#[derive(Serialize)]
+pub struct ImportSummary {
+  pub subscriptions: usize,
+  pub playlists: usize,
+  pub videos: usize,
+  pub history: usize,
+  pub preferences: bool
+}
+
+fn unescape_xml(value: &str) -> String {
+  value.replace("&quot;", "\"").replace("&apos;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+static OUTLINE_RE: OnceLock<Regex> = OnceLock::new();
+static ATTRIBUTE_RE: OnceLock<Regex> = OnceLock::new();
+
+fn parse_opml(contents: &str) -> Result<ImportedData, String> {
+  let outline_re = OUTLINE_RE.get_or_init(|| Regex::new(r#"<outline\b([^>]*)>"#).expect("the outline pattern is valid"));
+  let attribute_re = ATTRIBUTE_RE.get_or_init(|| Regex::new(r#"([A-Za-z_:]+)\s*=\s*"([^"]*)""#).expect("the attribute pattern is valid"));
+  let mut data = ImportedData::default();
+  for outline in outline_re.captures_iter(contents) {
+    let mut url = None;
+    let mut title = None;
+    for attribute in attribute_re.captures_iter(outline.get(1).unwrap().as_str()) {
+      let value = unescape_xml(attribute.get(2).unwrap().as_str());
+      match attribute.get(1).unwrap().as_str() {
+        "xmlUrl" => url = Some(value),
+        "title" => title = Some(value),
+        "text" if title.is_none() => title = Some(value),
+        _ => {}
+      }
+    }
+    if let Some(author_id) = url.as_deref().and_then(channel_link_or_id_to_id) {
+      data.subscriptions.push(Subscription { author_id, author: title.unwrap_or(String::from("")) });
+    }
+  }
+  Ok(data)
+}
+
+fn parse_newpipe(contents: &str) -> Result<ImportedData, String> {
+  let json = from_str::<Value>(contents).map_err(|error| format!("{}", error))?;
+  let mut data = ImportedData::default();
+  for subscription in json["subscriptions"].as_array().unwrap_or(&vec![]) {
+    // service 0 is yt
+    if subscription["service_id"].as_i64().unwrap_or(0) != 0 {
+      continue;
+    }
+    if let Some(author_id) = subscription["url"].as_str().and_then(channel_link_or_id_to_id) {
+      data.subscriptions.push(Subscription { author_id, author: String::from(subscription["name"].as_str().unwrap_or("")) });
+    }
+  }
+  Ok(data)
+}
+
+fn parse_freetube(contents: &str) -> Result<ImportedData, String> {
+  let mut data = ImportedData::default();
+  for line in contents.lines() {
+    if line.trim().is_empty() {
+      continue;
+    }
+    let entry = from_str::<Value>(line).map_err(|error| format!("{}", error))?;
+    if let Some(subscriptions) = entry["subscriptions"].as_array() {
+      // profile
+      for subscription in subscriptions {
+        if let Some(author_id) = subscription["id"].as_str().and_then(channel_link_or_id_to_id) {
+          data.subscriptions.push(Subscription { author_id, author: String::from(subscription["name"].as_str().unwrap_or("")) });
+        }
+      }
+    } else if let Some(videos) = entry["videos"].as_array() {
+      // playlist
+      data.playlists.push(UserPlaylist {
+        playlist_id: String::from(""),
+        title: String::from(entry["playlistName"].as_str().unwrap_or("")),
+        description: String::from(entry["description"].as_str().unwrap_or("")),
+        videos: videos.iter().filter_map(|video| video["videoId"].as_str().and_then(video_link_or_id_to_id)).collect(),
+        updated: entry["lastUpdatedAt"].as_i64().map(|updated| updated / 1000).unwrap_or(Utc::now().timestamp())
+      });
+    } else if let Some(video_id) = entry["videoId"].as_str().and_then(video_link_or_id_to_id) {
+      // history
+      data.history.push(HistoryEntry {
+        video_id,
+        watched: entry["timeWatched"].as_i64().map(|watched| watched / 1000).unwrap_or(0),
+        position: entry["watchProgress"].as_f64().map(|position| position as u64).unwrap_or(0)
+      });
+    }
+  }
+  data.history.sort_by_key(|entry| std::cmp::Reverse(entry.watched));
+  Ok(data)
+}
+
+fn parse_invidious(contents: &str) -> Result<ImportedData, String> {
+  let json = from_str::<Value>(contents).map_err(|error| format!("{}", error))?;
+  let mut data = ImportedData::default();
+  for subscription in json["subscriptions"].as_array().unwrap_or(&vec![]) {
+    if let Some(author_id) = subscription.as_str().and_then(channel_link_or_id_to_id) {
+      data.subscriptions.push(Subscription { author_id, author: String::from("") });
+    }
+  }
+  if let Some(history) = json["yaytapi_watch_history"].as_array() {
+    // exported by yaytapi with the watch times and resume positions (newest first)
+    data.history = history.iter()
+      .filter_map(|entry| from_value::<HistoryEntry>(entry.clone()).ok())
+      .filter(|entry| video_link_or_id_to_id(&entry.video_id).is_some())
+      .collect();
+  } else {
+    // iv keeps history oldest first
+    for video in json["watch_history"].as_array().unwrap_or(&vec![]).iter().rev() {
+      if let Some(video_id) = video.as_str().and_then(video_link_or_id_to_id) {
+        data.history.push(HistoryEntry { video_id, watched: 0, position: 0 });
+      }
+    }
+  }
+  for playlist in json["playlists"].as_array().unwrap_or(&vec![]) {
+    data.playlists.push(UserPlaylist {
+      playlist_id: String::from(""),
+      title: String::from(playlist["title"].as_str().unwrap_or("")),
+      description: String::from(playlist["description"].as_str().unwrap_or("")),
+      videos: playlist["videos"].as_array().unwrap_or(&vec![]).iter().filter_map(|video| video.as_str().and_then(video_link_or_id_to_id)).collect(),
+      updated: Utc::now().timestamp()
+    });
+  }
+  if json["preferences"].is_object() {
+    data.preferences = Some(json["preferences"].clone());
+  }
+  Ok(data)
+}
+
+// splits a csv row while respecting quoted columns
+fn split_csv_row(row: &str) -> Vec<String> {
+  let mut columns = vec![];
+  let mut column = String::new();
+  let mut in_quotes = false;
+  let mut chars = row.chars().peekable();
+  while let Some(c) = chars.next() {
+    match (c, in_quotes) {
+      ('"', true) => {
+        if chars.peek() == Some(&'"') {
+          column.push('"');
+          chars.next();
+        } else {
+          in_quotes = false;
+        }
+      },
+      ('"', false) => in_quotes = true,
+      (',', false) => columns.push(std::mem::take(&mut column)),
+      _ => column.push(c)
+    }
+  }
+  columns.push(column);
+  columns.into_iter().map(|column| String::from(column.trim())).collect()
+}
+
+fn parse_takeout(file_name: &str, contents: &str) -> Result<ImportedData, String> {
+  let mut data = ImportedData::default();
+  let rows = contents.trim_start_matches('\u{feff}').lines().map(split_csv_row).collect::<Vec::<Vec::<String>>>();
+  // older playlist exports start with a metadata block (`Playlist Id,Add new videos to top,Title,...`)
+  let mut title = None;
+  let mut description = None;
+  if rows.len() > 1 && rows[0].len() > 2 && rows[0][0].to_lowercase().starts_with("playlist id") {
+    title = rows[1].get(2).cloned();
+    description = rows[1].get(3).cloned();
+  }
+  let mut videos = vec![];
+  for row in &rows {
+    let Some(first) = row.first() else { continue };
+    if let Some(author_id) = channel_link_or_id_to_id(first) {
+      // subscriptions.csv: Channel Id,Channel Url,Channel Title
+      data.subscriptions.push(Subscription { author_id, author: row.get(2).cloned().unwrap_or(String::from("")) });
+    } else if first.len() == 11 {
+      if let Some(video_id) = video_link_or_id_to_id(first) {
+        videos.push(video_id);
+      }
+    }
+  }
+  if !videos.is_empty() {
+    // newer exports only have the videos, and the playlist is named after the file (`Favourites-videos.csv`)
+    let file_title = file_name.rsplit(['/', '\\']).next().unwrap_or("").trim_end_matches(".csv").trim_end_matches("-videos");
+    data.playlists.push(UserPlaylist {
+      playlist_id: String::from(""),
+      title: title.filter(|title| !title.is_empty()).unwrap_or(String::from(if file_title.is_empty() { "Imported playlist" } else { file_title })),
+      description: description.unwrap_or(String::from("")),
+      videos,
+      updated: Utc::now().timestamp()
+    });
+  }
+  Ok(data)
+}
+
+pub fn parse_import(format: &ImportFormat, file_name: &str, contents: &str) -> Result<ImportedData, String> {
+  match format {
+    ImportFormat::Opml => parse_opml(contents),
+    ImportFormat::NewPipe => parse_newpipe(contents),
+    ImportFormat::FreeTube => parse_freetube(contents),
+    ImportFormat::Invidious => parse_invidious(contents),
+    ImportFormat::Takeout => parse_takeout(file_name, contents)
+  }
+}
+
+// merges the imported data into what the user already has
+pub async fn apply_import(app_settings: &AppSettings, token: &str, data: ImportedData) -> ImportSummary {
+  let db = &app_settings.get_json_db().await;
+  let _lock = lock_user_data(token).await;
+  let mut summary = ImportSummary { subscriptions: 0, playlists: 0, videos: 0, history: 0, preferences: false };
+  if !data.subscriptions.is_empty() {
+    let mut subscriptions = get_subscriptions(db, token).await;
+    for subscription in data.subscriptions {
+      match subscriptions.iter_mut().find(|existing| existing.author_id == subscription.author_id) {
+        Some(existing) => {
+          if existing.author.is_empty() {
+            existing.author = subscription.author;
+          }
+        },
+        None => {
+          subscriptions.push(subscription);
+          summary.subscriptions += 1;
+        }
+      }
+    }
+    set_subscriptions(db, token, &subscriptions).await;
+  }
+  if !data.playlists.is_empty() {
+    let mut playlists = get_playlists(db, token).await;
+    for (i, playlist) in data.playlists.into_iter().enumerate() {
+      // playlists with the same title are merged so importing twice doesn't duplicate them
+      match playlists.iter_mut().find(|existing| existing.title == playlist.title) {
+        Some(existing) => {
+          for video_id in playlist.videos {
+            if !existing.videos.contains(&video_id) {
+              existing.videos.push(video_id);
+              summary.videos += 1;
+            }
+          }
+          existing.updated = Utc::now().timestamp();
+        },
+        None => {
+          summary.playlists += 1;
+          summary.videos += playlist.videos.len();
+          playlists.push(UserPlaylist {
+            playlist_id: format!("IVPL{:x}{}", Utc::now().timestamp_millis(), i),
+            ..playlist
+          });
+        }
+      }
+    }
+    set_playlists(db, token, &playlists).await;
+  }
+  if !data.history.is_empty() {
+    let mut history = get_history(db, token).await;
+    for entry in data.history {
+      if !history.iter().any(|existing| existing.video_id == entry.video_id) {
+        set_resume_position(db, token, &entry.video_id, entry.position).await;
+        history.push(entry);
+        summary.history += 1;
+      }
+    }
+    history.sort_by_key(|entry| std::cmp::Reverse(entry.watched));
+    set_history(db, token, &history, app_settings.max_history).await;
+  }
+  if let Some(preferences) = data.preferences {
+    set_preferences(db, token, &preferences).await;
+    summary.preferences = true;
+  }
+  summary
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ImportFormat, parse_import, split_csv_row};
+
+  const CHANNEL_ID: &str = "UC4QobU6STFB0P71PMvOGN5A";
+
+  #[test]
+  fn rejects_unknown_format_names() {
+    assert_eq!(ImportFormat::from_name("NewPipe"), Some(ImportFormat::NewPipe));
+    assert_eq!(ImportFormat::from_name("csv"), Some(ImportFormat::Takeout));
+    assert_eq!(ImportFormat::from_name("newpipee"), None);
+  }
+
+  #[test]
+  fn detects_formats() {
+    assert_eq!(ImportFormat::detect("subscriptions.opml", ""), Some(ImportFormat::Opml));
+    assert_eq!(ImportFormat::detect("", "<?xml version=\"1.0\"?><opml></opml>"), Some(ImportFormat::Opml));
+    assert_eq!(ImportFormat::detect("profiles.db", ""), Some(ImportFormat::FreeTube));
+    assert_eq!(ImportFormat::detect("subscriptions.csv", ""), Some(ImportFormat::Takeout));
+    assert_eq!(ImportFormat::detect("", r#"{ "app_version": "0.25.2", "subscriptions": [{ "service_id": 0, "url": "https://www.youtube.com/channel/UC4QobU6STFB0P71PMvOGN5A", "name": "jawed" }] }"#), Some(ImportFormat::NewPipe));
+    assert_eq!(ImportFormat::detect("", r#"{ "subscriptions": ["UC4QobU6STFB0P71PMvOGN5A"], "watch_history": [] }"#), Some(ImportFormat::Invidious));
+    assert_eq!(ImportFormat::detect("", "{\"videoId\":\"jNQXAC9IVRw\"}\n{\"videoId\":\"dQw4w9WgXcQ\"}"), Some(ImportFormat::FreeTube));
+    assert_eq!(ImportFormat::detect("", "not an export"), None);
+  }
+
+  #[test]
+  fn parses_opml() {
+    let opml = format!(r#"<opml version="1.1"><body><outline text="YouTube Subscriptions" title="YouTube Subscriptions"><outline text="jawed &amp; co" title="jawed &amp; co" type="rss" xmlUrl="https://www.youtube.com/feeds/videos.xml?channel_id={}" /></outline></body></opml>"#, CHANNEL_ID);
+    let data = parse_import(&ImportFormat::Opml, "", &opml).unwrap();
+    assert_eq!(data.subscriptions.len(), 1);
+    assert_eq!(data.subscriptions[0].author_id, CHANNEL_ID);
+    assert_eq!(data.subscriptions[0].author, "jawed & co");
+  }
+
+  #[test]
+  fn parses_newpipe_and_skips_other_services() {
+    let newpipe = format!(r#"{{ "subscriptions": [{{ "service_id": 0, "url": "https://www.youtube.com/channel/{}", "name": "jawed" }}, {{ "service_id": 1, "url": "https://soundcloud.com/someone", "name": "someone" }}] }}"#, CHANNEL_ID);
+    let data = parse_import(&ImportFormat::NewPipe, "", &newpipe).unwrap();
+    assert_eq!(data.subscriptions.len(), 1);
+    assert_eq!(data.subscriptions[0].author, "jawed");
+  }
+
+  #[test]
+  fn parses_freetube_profiles_playlists_and_history() {
+    let freetube = [
+      format!(r#"{{ "_id": "allChannels", "name": "All Channels", "subscriptions": [{{ "id": "{}", "name": "jawed" }}] }}"#, CHANNEL_ID),
+      String::from(r#"{ "_id": "favorites", "playlistName": "Favorites", "videos": [{ "videoId": "jNQXAC9IVRw" }], "lastUpdatedAt": 1700000000000 }"#),
+      String::from(r#"{ "videoId": "jNQXAC9IVRw", "timeWatched": 1600000000000, "watchProgress": 12.5 }"#),
+      String::from(r#"{ "videoId": "dQw4w9WgXcQ", "timeWatched": 1700000000000, "watchProgress": 0 }"#)
+    ].join("\n");
+    let data = parse_import(&ImportFormat::FreeTube, "", &freetube).unwrap();
+    assert_eq!(data.subscriptions.len(), 1);
+    assert_eq!(data.playlists.len(), 1);
+    assert_eq!(data.playlists[0].title, "Favorites");
+    assert_eq!(data.playlists[0].videos, vec![String::from("jNQXAC9IVRw")]);
+    assert_eq!(data.playlists[0].updated, 1700000000);
+    // newest first
+    assert_eq!(data.history.iter().map(|entry| entry.video_id.as_str()).collect::<Vec::<&str>>(), vec!["dQw4w9WgXcQ", "jNQXAC9IVRw"]);
+    assert_eq!(data.history[1].position, 12);
+  }
+
+  #[test]
+  fn parses_invidious() {
+    let invidious = format!(r#"{{ "subscriptions": ["{}"], "watch_history": ["jNQXAC9IVRw", "dQw4w9WgXcQ"], "preferences": {{ "dark_mode": "dark" }}, "playlists": [{{ "title": "Watch later", "description": "", "videos": ["jNQXAC9IVRw"] }}] }}"#, CHANNEL_ID);
+    let data = parse_import(&ImportFormat::Invidious, "", &invidious).unwrap();
+    assert_eq!(data.subscriptions[0].author_id, CHANNEL_ID);
+    // iv keeps history oldest first
+    assert_eq!(data.history[0].video_id, "dQw4w9WgXcQ");
+    assert_eq!(data.playlists[0].title, "Watch later");
+    assert!(data.preferences.is_some());
+  }
+
+  #[test]
+  fn parses_takeout_subscriptions_and_playlists() {
+    let subscriptions = format!("Channel Id,Channel Url,Channel Title\n{},http://www.youtube.com/channel/{},\"jawed, karim\"\n", CHANNEL_ID, CHANNEL_ID);
+    let data = parse_import(&ImportFormat::Takeout, "subscriptions.csv", &subscriptions).unwrap();
+    assert_eq!(data.subscriptions.len(), 1);
+    assert_eq!(data.subscriptions[0].author, "jawed, karim");
+    let playlist = "Video ID,Playlist Video Creation Timestamp\njNQXAC9IVRw,2023-01-01T00:00:00+00:00\n";
+    let data = parse_import(&ImportFormat::Takeout, "Takeout/YouTube/playlists/Favourites-videos.csv", playlist).unwrap();
+    assert_eq!(data.playlists.len(), 1);
+    assert_eq!(data.playlists[0].title, "Favourites");
+    assert_eq!(data.playlists[0].videos, vec![String::from("jNQXAC9IVRw")]);
+  }
+
+  #[test]
+  fn splits_quoted_csv_columns() {
+    assert_eq!(split_csv_row(r#"a,"b, c","say ""hi""""#), vec!["a", "b, c", "say \"hi\""]);
+  }
+}
@@ -0,0 +1,268 @@
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use crate::http::{HttpClient, HttpError, Egress};
+use crate::sessions::is_bot_check;
+use actix_web::HttpRequest;
+use urlencoding::encode;
+
+// the language (`hl`) and region (`gl`) innertube should return content for
+#[derive(Clone)]
+pub struct ContentLocale {
+  pub hl: String,
+  pub gl: String
+}
+
+impl Default for ContentLocale {
+  fn default() -> ContentLocale {
+    ContentLocale { hl: String::from("en"), gl: String::from("US") }
+  }
+}
+
+impl ContentLocale {
+  // `hl` falls back to the first language in `Accept-Language`, and `region` is the iv name for `gl`
+  pub fn from_request(req: &HttpRequest, hl: Option<&str>, region: Option<&str>, gl: Option<&str>) -> ContentLocale {
+    let default = ContentLocale::default();
+    let hl = match hl {
+      Some(hl) => String::from(hl),
+      None => match req.headers().get("Accept-Language").and_then(|header| header.to_str().ok()) {
+        Some(accept_language) => {
+          // ex: `en-GB,en;q=0.9` -> `en-GB`
+          let first = accept_language.split(',').next().unwrap_or("").split(';').next().unwrap_or("").trim();
+          if first.is_empty() || first == "*" {
+            default.hl
+          } else {
+            String::from(first)
+          }
+        },
+        None => default.hl
+      }
+    };
+    let gl = match region.or(gl) {
+      Some(gl) => gl.to_uppercase(),
+      None => default.gl
+    };
+    ContentLocale { hl, gl }
+  }
+  // appended to every cache key so different locales never share an entry
+  pub fn cache_key(&self) -> String {
+    format!("{}-{}", self.hl, self.gl)
+  }
+}
+
+// yayti's extractors can only set `hl` (and send through their own http client), so the client contexts are built here
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum InnertubeClient {
+  Web,
+  Android,
+  Ios,
+  // the embedded tv player, which can play most age restricted videos
+  TvEmbedded,
+  // the embedded web player (`/embed/{video_id}`)
+  WebEmbedded
+}
+
+impl InnertubeClient {
+  // the names used by `--player-clients`
+  pub fn from_name(name: &str) -> Option<InnertubeClient> {
+    match name.to_lowercase().as_str() {
+      "web" => Some(InnertubeClient::Web),
+      "android" => Some(InnertubeClient::Android),
+      "ios" => Some(InnertubeClient::Ios),
+      "tv_embedded" => Some(InnertubeClient::TvEmbedded),
+      "web_embedded" => Some(InnertubeClient::WebEmbedded),
+      _ => None
+    }
+  }
+  pub fn name(&self) -> &str {
+    match self {
+      InnertubeClient::Web => "web",
+      InnertubeClient::Android => "android",
+      InnertubeClient::Ios => "ios",
+      InnertubeClient::TvEmbedded => "tv_embedded",
+      InnertubeClient::WebEmbedded => "web_embedded"
+    }
+  }
+  // the browser based clients get `signatureCipher`s instead of urls (the apps get plain urls)
+  pub fn needs_deciphering(&self) -> bool {
+    match self {
+      InnertubeClient::Web | InnertubeClient::TvEmbedded | InnertubeClient::WebEmbedded => true,
+      InnertubeClient::Android | InnertubeClient::Ios => false
+    }
+  }
+  fn client_name(&self) -> &str {
+    match self {
+      InnertubeClient::Web => "WEB",
+      InnertubeClient::Android => "ANDROID",
+      InnertubeClient::Ios => "IOS",
+      InnertubeClient::TvEmbedded => "TVHTML5_SIMPLY_EMBEDDED_PLAYER",
+      InnertubeClient::WebEmbedded => "WEB_EMBEDDED_PLAYER"
+    }
+  }
+  fn client_name_id(&self) -> &str {
+    match self {
+      InnertubeClient::Web => "1",
+      InnertubeClient::Android => "3",
+      InnertubeClient::Ios => "5",
+      InnertubeClient::TvEmbedded => "85",
+      InnertubeClient::WebEmbedded => "56"
+    }
+  }
+  fn client_version(&self) -> &str {
+    match self {
+      InnertubeClient::Web => "2.20250312.04.00",
+      InnertubeClient::Android => "20.10.38",
+      InnertubeClient::Ios => "20.10.4",
+      InnertubeClient::TvEmbedded => "2.0",
+      InnertubeClient::WebEmbedded => "1.20250310.01.00"
+    }
+  }
+  fn user_agent(&self) -> &str {
+    match self {
+      InnertubeClient::Web | InnertubeClient::TvEmbedded | InnertubeClient::WebEmbedded => "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.36",
+      InnertubeClient::Android => "com.google.android.youtube/20.10.38 (Linux; U; Android 11) gzip",
+      InnertubeClient::Ios => "com.google.ios.youtube/20.10.4 (iPhone16,2; U; CPU iOS 18_3_2 like Mac OS X;)"
+    }
+  }
+  pub fn context(&self, locale: &ContentLocale) -> Value {
+    let mut client = json!({
+      "clientName": self.client_name(),
+      "clientVersion": self.client_version(),
+      "hl": locale.hl,
+      "gl": locale.gl
+    });
+    match self {
+      InnertubeClient::Android => {
+        client["androidSdkVersion"] = json!(30);
+        client["osName"] = json!("Android");
+        client["osVersion"] = json!("11");
+      },
+      InnertubeClient::Ios => {
+        client["deviceMake"] = json!("Apple");
+        client["deviceModel"] = json!("iPhone16,2");
+        client["osName"] = json!("iOS");
+        client["osVersion"] = json!("18.3.2.22D82");
+      },
+      InnertubeClient::Web | InnertubeClient::TvEmbedded | InnertubeClient::WebEmbedded => {}
+    }
+    match self {
+      // embedded players have to say where they're embedded
+      InnertubeClient::TvEmbedded | InnertubeClient::WebEmbedded => json!({ "client": client, "thirdParty": { "embedUrl": "https://www.youtube.com/" } }),
+      _ => json!({ "client": client })
+    }
+  }
+}
+
+// POSTs to `/youtubei/v1/{endpoint}` with the client context filled in, through the given egress (see `HttpClient::pick_egress`)
+// as one of the pooled sessions created from that egress (see `sessions::SessionPool`), which is retired if innertube asks it to prove it's not a bot
+async fn post(http: &HttpClient, egress: Egress, endpoint: &str, client: &InnertubeClient, locale: &ContentLocale, mut body: Value) -> Result<String, HttpError> {
+  body["context"] = client.context(locale);
+  let session = http.sessions.pick(&egress);
+  let mut client_version = String::from(client.client_version());
+  if let Some(session) = &session {
+    body["context"]["client"]["visitorData"] = json!(session.visitor_data);
+    // the homepage only knows the web client's version
+    if let (InnertubeClient::Web, Some(session_client_version)) = (client, &session.client_version) {
+      client_version = String::from(session_client_version);
+      body["context"]["client"]["clientVersion"] = json!(client_version);
+    }
+  }
+  let mut request = http.egress_client(&egress)
+    .post(format!("{}/youtubei/v1/{}?prettyPrint=false", http.innertube_url, endpoint))
+    .header("Content-Type", "application/json")
+    .header("User-Agent", client.user_agent())
+    .header("X-YouTube-Client-Name", client.client_name_id())
+    .header("X-YouTube-Client-Version", client_version)
+    .header("Origin", "https://www.youtube.com");
+  if let Some(session) = &session {
+    request = request
+      .header("X-Goog-Visitor-Id", &session.visitor_data)
+      .header("Cookie", &session.cookies);
+  }
+  // innertube's POSTs only read, so they're safe to retry
+  let response = http.send(request.body(body.to_string())).await?;
+  let status = response.status();
+  let text = response.text().await?;
+  if let Some(session) = &session {
+    if is_bot_check(status, &text) {
+      http.sessions.retire(&session.id);
+    }
+  }
+  Ok(text)
+}
+
+// stream urls only work from the ip that fetched them, so the caller picks (and remembers) the egress
+pub async fn fetch_player(http: &HttpClient, egress: Egress, id: &str, signature_timestamp: Option<i32>, client: &InnertubeClient, locale: &ContentLocale) -> Result<String, HttpError> {
+  let mut body = json!({
+    "videoId": id,
+    "contentCheckOk": true,
+    "racyCheckOk": true
+  });
+  if let Some(signature_timestamp) = signature_timestamp {
+    body["playbackContext"] = json!({ "contentPlaybackContext": { "signatureTimestamp": signature_timestamp } });
+  }
+  post(http, egress, "player", client, locale, body).await
+}
+
+pub async fn fetch_next(http: &HttpClient, id: &str, locale: &ContentLocale) -> Result<String, HttpError> {
+  post(http, http.pick_egress(), "next", &InnertubeClient::Web, locale, json!({ "videoId": id })).await
+}
+
+pub async fn fetch_playlist(http: &HttpClient, id: &str, locale: &ContentLocale) -> Result<String, HttpError> {
+  post(http, http.pick_egress(), "browse", &InnertubeClient::Web, locale, json!({ "browseId": format!("VL{}", id) })).await
+}
+
+pub async fn fetch_continuation(http: &HttpClient, endpoint: &str, token: &str, locale: &ContentLocale) -> Result<String, HttpError> {
+  post(http, http.pick_egress(), endpoint, &InnertubeClient::Web, locale, json!({ "continuation": token })).await
+}
+
+// `params` selects a tab (ex: the videos tab of a channel)
+pub async fn fetch_browse(http: &HttpClient, browse_id: &str, params: Option<&str>, locale: &ContentLocale) -> Result<String, HttpError> {
+  let mut body = json!({ "browseId": browse_id });
+  if let Some(params) = params {
+    body["params"] = json!(params);
+  }
+  post(http, http.pick_egress(), "browse", &InnertubeClient::Web, locale, body).await
+}
+
+pub async fn fetch_search(http: &HttpClient, query: &str, locale: &ContentLocale) -> Result<String, HttpError> {
+  post(http, http.pick_egress(), "search", &InnertubeClient::Web, locale, json!({ "query": query })).await
+}
+
+// the shorts player, which returns the params for the rest of the reel feed
+pub async fn fetch_reel_item_watch(http: &HttpClient, id: &str, locale: &ContentLocale) -> Result<String, HttpError> {
+  post(http, http.pick_egress(), "reel/reel_item_watch", &InnertubeClient::Web, locale, json!({ "playerRequest": { "videoId": id }, "params": "CAUwAg%3D%3D" })).await
+}
+
+// `sequence_params` is either `sequenceContinuation` from `reel/reel_item_watch` or a continuation token from a previous sequence
+pub async fn fetch_reel_watch_sequence(http: &HttpClient, sequence_params: &str, locale: &ContentLocale) -> Result<String, HttpError> {
+  post(http, http.pick_egress(), "reel/reel_watch_sequence", &InnertubeClient::Web, locale, json!({ "sequenceParams": sequence_params })).await
+}
+
+// search suggestions aren't part of innertube, but they still follow the locale (and `--innertube-url`, when it's set)
+pub async fn fetch_suggestions(http: &HttpClient, query: &str, locale: &ContentLocale) -> Result<String, HttpError> {
+  let suggestions_url = match http.innertube_url.as_str() {
+    "https://www.youtube.com" => "https://suggestqueries-clients6.youtube.com",
+    innertube_url => innertube_url
+  };
+  http.send(http.egress_client(&http.pick_egress()).get(format!("{}/complete/search?client=firefox&ds=yt&q={}&hl={}&gl={}", suggestions_url, encode(query), encode(&locale.hl), encode(&locale.gl))))
+    .await?
+    .text()
+    .await
+    .map_err(HttpError::from)
+}
+
+// the id of the current player.js, from the iframe api (ex: `https:\/\/www.youtube.com\/s\/player\/4c3f79c5\/www-widgetapi.vflset\/www-widgetapi.js`)
+pub async fn fetch_player_js_id(http: &HttpClient) -> Result<Option<String>, HttpError> {
+  let iframe_api = http.send(http.client.get(format!("{}/iframe_api", http.innertube_url))).await?.text().await?;
+  let Ok(player_js_id_re) = Regex::new(r#"player\\?/([0-9a-zA-Z_-]+)\\?/"#) else { todo!() };
+  Ok(player_js_id_re.captures(&iframe_api).map(|player_js_id_captures| String::from(player_js_id_captures.get(1).unwrap().as_str())))
+}
+
+pub async fn fetch_player_js(http: &HttpClient, player_js_id: &str) -> Result<String, HttpError> {
+  http.send(http.client.get(format!("{}/s/player/{}/player_ias.vflset/en_US/base.js", http.innertube_url, encode(player_js_id))))
+    .await?
+    .text()
+    .await
+    .map_err(HttpError::from)
+}
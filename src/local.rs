@@ -1,41 +1,48 @@
 
+use std::sync::OnceLock;
 use chrono::Utc;
+use regex::Regex;
 use serde_json::{Value};
 use yayti::{parsers::web::playlist::{Playlist,PlaylistVideo}, helpers::AuthorThumbnail};
-use crate::{routes::video::fetch_player_with_cache, settings::AppSettings};
+use crate::{routes::video::fetch_player_with_cache, settings::AppSettings, innertube::ContentLocale};
 
-fn video_link_or_id_to_id(link_like_video_id: &str) -> &str {
-  if link_like_video_id.contains("/watch?v=") {
-    match link_like_video_id.split("/watch?v=").nth(1) {
-      Some(video_id) => {
-        video_id
-      },
-      None => {
-        link_like_video_id
-      }
-    }
-  } else if link_like_video_id.contains("youtu.be/") {
-    match link_like_video_id.split("youtu.be/").nth(1) {
-      Some(video_id) => {
-        video_id
-      },
-      None => {
-        link_like_video_id
-      }
-    }
-  } else {
-    link_like_video_id
+static VIDEO_ID_RE: OnceLock<Regex> = OnceLock::new();
+static VIDEO_LINK_RE: OnceLock<Regex> = OnceLock::new();
+static CHANNEL_ID_RE: OnceLock<Regex> = OnceLock::new();
+
+// sus out the video id from any yt, iv, or yaytapi video link (or a bare video id)
+// ex:
+//  https://www.youtube.com/watch?v=PxeFyxrUWt0&t=10 -> PxeFyxrUWt0
+//  https://youtu.be/PxeFyxrUWt0?si=abc -> PxeFyxrUWt0
+//  https://youtube.com/shorts/PxeFyxrUWt0 -> PxeFyxrUWt0
+//  https://redirect.invidious.io/embed/PxeFyxrUWt0 -> PxeFyxrUWt0
+pub fn video_link_or_id_to_id(link_like_video_id: &str) -> Option<String> {
+  let link_like_video_id = link_like_video_id.trim();
+  let id_re = VIDEO_ID_RE.get_or_init(|| Regex::new(r#"^[A-Za-z0-9_-]{11}$"#).expect("the video id pattern is valid"));
+  if id_re.is_match(link_like_video_id) {
+    return Some(String::from(link_like_video_id));
   }
+  let link_re = VIDEO_LINK_RE.get_or_init(|| Regex::new(r#"(?:[?&]v=|/shorts/|/embed/|/live/|/v/|/e/|/watch/|youtu\.be/|/vi/)([A-Za-z0-9_-]{11})(?:[^A-Za-z0-9_-]|$)"#).expect("the video link pattern is valid"));
+  link_re.captures(link_like_video_id).map(|captures| String::from(captures.get(1).unwrap().as_str()))
+}
+
+// sus out the channel id from a channel link, feed url, or bare channel id
+// ex:
+//  https://www.youtube.com/channel/UCXuqSBlHAE6Xw-yeJA0Tunw -> UCXuqSBlHAE6Xw-yeJA0Tunw
+//  https://www.youtube.com/feeds/videos.xml?channel_id=UCXuqSBlHAE6Xw-yeJA0Tunw -> UCXuqSBlHAE6Xw-yeJA0Tunw
+pub fn channel_link_or_id_to_id(link_like_channel_id: &str) -> Option<String> {
+  let channel_re = CHANNEL_ID_RE.get_or_init(|| Regex::new(r#"(?:^|[/=])(UC[A-Za-z0-9_-]{22})(?:[^A-Za-z0-9_-]|$)"#).expect("the channel id pattern is valid"));
+  channel_re.captures(link_like_channel_id.trim()).map(|captures| String::from(captures.get(1).unwrap().as_str()))
 }
 
 async fn array_item_into_video(item: &Value, index: u32, app_settings: &AppSettings) -> Option<PlaylistVideo> {
   match item.as_str() {
     Some(item) => {
-      let video_id = video_link_or_id_to_id(&item);
+      let video_id = &video_link_or_id_to_id(item)?;
       let mut app_settings = app_settings.clone();
       app_settings.cache_timeout = u64::MAX;// don't be picky with fetching these videos from cache
       // we don't need anything time sensitive like streaming data, just basic info
-      let Ok(next_value) = fetch_player_with_cache(video_id, "en", &app_settings, false, Some(&app_settings.pub_url.clone().unwrap_or(format!("{}:{}", app_settings.ip_address, app_settings.port)))).await else { return None };
+      let Ok(next_value) = fetch_player_with_cache(video_id, &ContentLocale::default(), &app_settings, false, Some(&app_settings.pub_url.clone().unwrap_or(format!("{}:{}", app_settings.ip_address, app_settings.port)))).await else { return None };
       Some(PlaylistVideo {
         title: yayti::parsers::web::video::get_title(&next_value),
         video_id: Some(String::from(video_id)),
@@ -62,18 +69,15 @@ pub async fn local_playlist_to_iv(title: &str, playlist_json: &Value, app_settin
       None => vec![]
     }
   };
-  if items.len() > 0 {
+  if !items.is_empty() {
     let mut i = 0;
     // playlist of video ids / links containing video ids
     let mut videos = vec![];
     for item in items {
       i+=1;
-      match array_item_into_video(&item, i, app_settings).await {
-        Some(video) => {
-          videos.push(video);
-        },
-        None => {}
-      };
+      if let Some(video) = array_item_into_video(&item, i, app_settings).await {
+        videos.push(video);
+      }
     }
     let videos_count = videos.len() as i32;
     Some(Playlist {
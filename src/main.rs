@@ -3,10 +3,24 @@ mod settings;
 mod routes;
 mod helpers;
 mod local;
+mod import;
+mod export;
+mod innertube;
+mod parsers;
+mod fallback;
+mod state;
+mod peers;
+mod http;
+mod circuit;
+mod recording;
+mod sessions;
+mod player_js;
 use local::local_playlist_to_iv;
+use import::{ImportFormat, parse_import, apply_import};
+use regex::Regex;
 use serde_json::{to_string_pretty, from_str, Value, json};
 use settings::AppSettings;
-use actix_web::{HttpServer, App, web::Data};
+use actix_web::{HttpServer, App, web::{Data, PayloadConfig}};
 use std::{io::Result, fs};
 use actix_web::middleware::Logger;
 use actix_cors::Cors;
@@ -18,10 +32,74 @@ use mimalloc::MiMalloc;
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
+// `yaytapi import --token=<token> [--format=<format>] <file> [<file> ...]`
+// failures go to stderr, and it returns false if anything failed so the exit code can say so
+async fn import_from_cli(args: &[String], app_settings: &AppSettings) -> bool {
+  let args_string = args.join(" ");
+  let token_re = Regex::new(r#"--token=([^ ]+)"#).expect("the token pattern is valid");
+  let Some(token) = token_re.captures(&args_string).map(|captures| String::from(captures.get(1).unwrap().as_str())) else {
+    eprintln!("usage: yaytapi import --token=<token> [--format=opml|newpipe|freetube|invidious|takeout] <file> [<file> ...]");
+    return false;
+  };
+  let format_re = Regex::new(r#"--format=([^ ]+)"#).expect("the format pattern is valid");
+  let format_name = format_re.captures(&args_string).map(|captures| String::from(captures.get(1).unwrap().as_str()));
+  // an unknown format is a typo, not a reason to guess
+  let forced_format = match format_name.as_deref() {
+    Some(format_name) => match ImportFormat::from_name(format_name) {
+      Some(format) => Some(format),
+      None => {
+        eprintln!("❌ Unknown import format: {} (expected opml, newpipe, freetube, invidious or takeout)", format_name);
+        return false;
+      }
+    },
+    None => None
+  };
+  let mut is_success = true;
+  for file_name in args.iter().skip(2).filter(|arg| !arg.starts_with("--")) {
+    let contents = match fs::read(file_name) {
+      Ok(bytes) => format!("{}", String::from_utf8_lossy(&bytes)),
+      Err(error) => {
+        eprintln!("❌ {}: {}", file_name, error);
+        is_success = false;
+        continue;
+      }
+    };
+    let format = match forced_format.clone().or_else(|| ImportFormat::detect(file_name, &contents)) {
+      Some(format) => format,
+      None => {
+        eprintln!("❌ {}: unable to detect the format, please specify `--format`", file_name);
+        is_success = false;
+        continue;
+      }
+    };
+    match parse_import(&format, file_name, &contents) {
+      Ok(data) => {
+        let summary = apply_import(app_settings, &token, data).await;
+        println!("✅ {}: {} subscriptions, {} playlists ({} videos), {} history entries imported", file_name, summary.subscriptions, summary.playlists, summary.videos, summary.history);
+      },
+      Err(error) => {
+        eprintln!("❌ {}: {}", file_name, error);
+        is_success = false;
+      }
+    }
+  }
+  is_success
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
   let args: Vec<String> = std::env::args().collect();
+  // before the settings are parsed, so warnings about them (ex: an invalid `--proxy`) are logged
+  if !args.contains(&String::from("--no-logs")) {
+    init_from_env(Env::default().default_filter_or("info"));
+  }
   let app_settings = AppSettings::from_cli_args(&args);
+  if args.get(1).map(|arg| arg == "import").unwrap_or(false) {
+    if !import_from_cli(&args, &app_settings).await {
+      std::process::exit(1);
+    }
+    return Ok(());
+  }
   let Ok(app_settings_str) = to_string_pretty(&app_settings) else { todo!() };
   if app_settings.print_config {
     println!("config:");
@@ -31,85 +109,98 @@ async fn main() -> Result<()> {
   }
   let ip_address = String::from(&app_settings.ip_address);
   let port = String::from(&app_settings.port);
-  if app_settings.enable_actix_web_logger {
-    init_from_env(Env::default().default_filter_or("info"));
-  }
-  match &app_settings.playlists_path {
-    Some(path) => {
-      log::info!("Loading custom playlists . . .");
-      let directory_paths = std::fs::read_dir(path).map(|res| {
-        let mut directory: Vec::<String> = vec![];
-        for entry in res {
-          match entry {
-            Ok(entry) => {
-              match entry.file_name().into_string() {
-                Ok(str) => directory.push(str),
-                Err(_) => {}
-              }
-            },
-            Err(_) => {
-
-            }
-          }
+  if let Some(path) = &app_settings.playlists_path {
+    log::info!("Loading custom playlists . . .");
+    let directory_paths = std::fs::read_dir(path).map(|res| {
+      let mut directory: Vec::<String> = vec![];
+      for entry in res.flatten() {
+        if let Ok(str) = entry.file_name().into_string() {
+          directory.push(str)
         }
-        directory
-      });
-      for file_name in directory_paths.unwrap() {
-        if file_name.ends_with(".json") {
-          // only scan json files directly in the top level
-          let local_uri = format!("{}/{}", path, file_name);
-          match fs::read(&local_uri).map(|res| {
-            format!("{}", String::from_utf8_lossy(res.as_ref()))
-          }) {
-            Ok(file_contents) => {
-              match from_str::<Value>(&file_contents) {
-                Ok(resulting_json) => {
-                  let db = &app_settings.get_json_db().await;
-                  match db.seek_for_json("local-playlist", &file_name).await {
-                    Some(_) => {
-                      log::info!("Skipping because already loaded into db: {}", &local_uri);
-                    },
-                    None => {
-                      let playlist = local_playlist_to_iv(&file_name, &resulting_json, &app_settings).await;
-                      // println!("{:#?}", playlist.map(|p| p.into_inv()));
-                      let json = json!(playlist.map(|p| p.into_inv()));
-                      db.insert_json("local-playlist", &file_name, &json).await;
-                      log::info!("Loaded: {}", &local_uri);
-                    }
+      }
+      directory
+    });
+    for file_name in directory_paths.unwrap() {
+      if file_name.ends_with(".json") {
+        // only scan json files directly in the top level
+        let local_uri = format!("{}/{}", path, file_name);
+        match fs::read(&local_uri).map(|res| {
+          format!("{}", String::from_utf8_lossy(res.as_ref()))
+        }) {
+          Ok(file_contents) => {
+            match from_str::<Value>(&file_contents) {
+              Ok(resulting_json) => {
+                let db = &app_settings.get_json_db().await;
+                match db.seek_for_json("local-playlist", &file_name).await {
+                  Some(_) => {
+                    log::info!("Skipping because already loaded into db: {}", &local_uri);
+                  },
+                  None => {
+                    let playlist = local_playlist_to_iv(&file_name, &resulting_json, &app_settings).await;
+                    // println!("{:#?}", playlist.map(|p| p.into_inv()));
+                    let json = json!(playlist.map(|p| p.into_inv()));
+                    db.insert_json("local-playlist", &file_name, &json).await;
+                    log::info!("Loaded: {}", &local_uri);
                   }
-                },
-                Err(error) => {
-                  log::error!("{}", error)
                 }
+              },
+              Err(error) => {
+                log::error!("{}", error)
               }
-            },
-            Err(error) => {
-              log::error!("{}", error)
             }
+          },
+          Err(error) => {
+            log::error!("{}", error)
           }
-          
         }
+        
       }
-    },
-    None => {}
-  };
+    }
+  }
   let workers = app_settings.num_of_workers;
+  peers::spawn_health_checks(app_settings.clone());
+  circuit::spawn_stale_purge(app_settings.clone());
+  sessions::spawn_session_keeper(app_settings.clone());
+  player_js::spawn_player_js_watcher(app_settings.clone());
   HttpServer::new(move || {
     let enable_cors = app_settings.enable_cors;
-    let app_settings = (&app_settings).clone();
+    let app_settings = app_settings.clone();
+    let piped_prefix = app_settings.piped_prefix.clone();
     App::new()
       .wrap(Logger::default())
       .app_data(Data::new(app_settings))
+      // imports (freetube history especially) can be much larger than the default payload limit
+      .app_data(PayloadConfig::new(32 * 1024 * 1024))
       .service(routes::server_stats)// -> /api/v1/stats
+      .service(routes::cache::cache_endpoint)// -> /api/v1/cache/{collection}/{key}
       .service(routes::video::latest_version)// -> /latest_version
       .service(routes::video::videoplayback)// -> /videoplayback
       .service(routes::video::decipher_stream)// -> /decipher_stream
       .service(routes::video::video_endpoint)// -> /api/v1/videos/{video_id}
+      .service(routes::video::annotations_endpoint)// -> /api/v1/annotations/{video_id}
+      .service(routes::manifest::dash_manifest)// -> /api/manifest/dash/id/{video_id}
+      .service(routes::shorts::sequence_endpoint)// -> /api/v1/shorts/{video_id}/sequence
       .service(routes::video::video_thumbnail_proxy)// -> /vi/{video_id}/{file_name}.jpg
       .service(routes::channel::author_thumbnail_proxy)// -> /ggpht/{author_thumbnail_url:.*}
       .service(routes::playlist::playlist_endpoint)
+      .service(routes::auth::list_history)// -> GET /api/v1/auth/history
+      .service(routes::auth::add_history)// -> POST /api/v1/auth/history/{video_id}
+      .service(routes::auth::delete_history)// -> DELETE /api/v1/auth/history/{video_id}
+      .service(routes::auth::clear_history)// -> DELETE /api/v1/auth/history
+      .service(routes::auth::list_subscriptions)// -> /api/v1/auth/subscriptions
+      .service(routes::auth::list_playlists)// -> /api/v1/auth/playlists
+      .service(routes::auth::import_data)// -> POST /api/v1/auth/import
+      .service(routes::auth::export_data)// -> /api/v1/auth/export
       .service(routes::static_files)
       .service(routes::homepage)
+      .service(routes::pages::watch_page)// -> /watch?v={video_id}
+      .service(routes::pages::playlist_page)// -> /playlist?list={playlist_id}
+      .service(routes::pages::channel_page)// -> /channel/{channel_id}
+      .service(routes::pages::search_page)// -> /search?q={query}
+      .service(routes::pages::embed_page)// -> /embed/{video_id}
+      .service(routes::pages::shorts_page)// -> /shorts/{video_id}
+      .service(routes::pages::oembed)// -> /oembed?url={url}
+      .configure(|cfg| routes::piped::configure(cfg, piped_prefix.as_deref()))// -> {piped_prefix}/*
       .service(routes::not_found)
       .wrap(
         if enable_cors {
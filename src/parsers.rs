@@ -0,0 +1,9 @@
+// parsers for the parts of innertube responses yayti doesn't cover yet
+pub mod renderers;
+pub mod search;
+pub mod channel;
+pub mod chapters;
+pub mod heatmap;
+pub mod annotations;
+pub mod shorts;
+pub mod piped;
@@ -0,0 +1,83 @@
+use serde_json::{json, Value, Map};
+use urlencoding::decode;
+use crate::parsers::renderers::{get_text, get_thumbnails};
+
+// external links go through `https://www.youtube.com/redirect?q=<url>`
+fn unwrap_redirect(url: &str) -> String {
+  match url.split_once("/redirect?").map(|(_, query)| query.split('&').find_map(|param| param.strip_prefix("q="))) {
+    Some(Some(target)) => decode(target).map(|target| target.into_owned()).unwrap_or(String::from(target)),
+    _ => String::from(url)
+  }
+}
+
+// what an element points at: `{ "type": "video" | "playlist" | "channel" | "link", ... }`
+fn endpoint_to_target(endpoint: &Value) -> Option<Value> {
+  let watch_endpoint = &endpoint["watchEndpoint"];
+  if watch_endpoint.is_object() {
+    return match watch_endpoint["playlistId"].as_str() {
+      Some(playlist_id) => Some(json!({ "type": "playlist", "playlistId": playlist_id, "videoId": watch_endpoint["videoId"] })),
+      None => Some(json!({ "type": "video", "videoId": watch_endpoint["videoId"].as_str()? }))
+    };
+  }
+  if let Some(browse_id) = endpoint["browseEndpoint"]["browseId"].as_str() {
+    return Some(match browse_id.strip_prefix("VL") {
+      Some(playlist_id) => json!({ "type": "playlist", "playlistId": playlist_id }),
+      None => json!({ "type": "channel", "authorId": browse_id })
+    });
+  }
+  let url = endpoint["urlEndpoint"]["url"].as_str()?;
+  Some(json!({ "type": "link", "url": unwrap_redirect(url) }))
+}
+
+fn card_to_inv(card: &Value) -> Option<Map<String, Value>> {
+  let renderer = &card["cardRenderer"];
+  let content = renderer["content"].as_object()?;
+  let (content_type, content) = content.iter().next()?;
+  let (title, target) = match content_type.as_str() {
+    "videoInfoCardContentRenderer" => (get_text(&content["videoTitle"]), endpoint_to_target(&content["action"])),
+    "playlistInfoCardContentRenderer" => (get_text(&content["playlistTitle"]), endpoint_to_target(&content["action"])),
+    "simpleCardContentRenderer" => (get_text(&content["title"]), endpoint_to_target(&content["command"])),
+    "collaboratorInfoCardContentRenderer" => (get_text(&content["channelName"]), endpoint_to_target(&content["endpoint"])),
+    _ => (None, None)
+  };
+  let thumbnail = ["videoThumbnail", "playlistThumbnail", "image", "channelAvatar"].iter().map(|key| &content[*key]).find(|thumbnail| thumbnail.is_object()).unwrap_or(&Value::Null);
+  let cue_range = &renderer["cueRanges"][0];
+  let mut output = Map::<String, Value>::new();
+  output.insert(String::from("title"), json!(title.unwrap_or(String::from(""))));
+  output.insert(String::from("teaser"), json!(get_text(&renderer["teaser"]["simpleCardTeaserRenderer"]["message"]).unwrap_or(String::from(""))));
+  output.insert(String::from("startMs"), json!(cue_range["startCardActiveMs"].as_str().and_then(|ms| ms.parse::<i64>().ok()).unwrap_or(0)));
+  output.insert(String::from("endMs"), json!(cue_range["endCardActiveMs"].as_str().and_then(|ms| ms.parse::<i64>().ok()).unwrap_or(0)));
+  output.insert(String::from("thumbnails"), get_thumbnails(thumbnail));
+  output.insert(String::from("target"), target?);
+  Some(output)
+}
+
+fn endscreen_element_to_inv(element: &Value) -> Option<Map<String, Value>> {
+  let renderer = &element["endscreenElementRenderer"];
+  // subscribe buttons point at the uploader's channel
+  let target = endpoint_to_target(&renderer["endpoint"])?;
+  let mut output = Map::<String, Value>::new();
+  output.insert(String::from("style"), json!(renderer["style"].as_str().unwrap_or("").to_lowercase()));
+  output.insert(String::from("title"), json!(get_text(&renderer["title"]).unwrap_or(String::from(""))));
+  output.insert(String::from("metadata"), json!(get_text(&renderer["metadata"]).unwrap_or(String::from(""))));
+  output.insert(String::from("startMs"), json!(renderer["startMs"].as_str().and_then(|ms| ms.parse::<i64>().ok()).unwrap_or(0)));
+  output.insert(String::from("endMs"), json!(renderer["endMs"].as_str().and_then(|ms| ms.parse::<i64>().ok()).unwrap_or(0)));
+  // position and size as fractions of the player
+  output.insert(String::from("left"), json!(renderer["left"].as_f64().unwrap_or(0.0)));
+  output.insert(String::from("top"), json!(renderer["top"].as_f64().unwrap_or(0.0)));
+  output.insert(String::from("width"), json!(renderer["width"].as_f64().unwrap_or(0.0)));
+  output.insert(String::from("aspectRatio"), json!(renderer["aspectRatio"].as_f64().unwrap_or(0.0)));
+  output.insert(String::from("thumbnails"), get_thumbnails(&renderer["image"]));
+  output.insert(String::from("target"), target);
+  Some(output)
+}
+
+// the player response's `cards` and `endscreen`, which replaced annotations
+pub fn get_annotations(player: &Value) -> Map<String, Value> {
+  let empty_vec = vec![];
+  let mut output = Map::<String, Value>::new();
+  output.insert(String::from("videoId"), player["videoDetails"]["videoId"].clone());
+  output.insert(String::from("cards"), json!(player["cards"]["cardCollectionRenderer"]["cards"].as_array().unwrap_or(&empty_vec).iter().filter_map(card_to_inv).collect::<Vec::<Map<String, Value>>>()));
+  output.insert(String::from("endscreen"), json!(player["endscreen"]["endscreenRenderer"]["elements"].as_array().unwrap_or(&empty_vec).iter().filter_map(endscreen_element_to_inv).collect::<Vec::<Map<String, Value>>>()));
+  output
+}
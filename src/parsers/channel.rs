@@ -0,0 +1,43 @@
+use serde_json::{json, Value, Map};
+use crate::parsers::renderers::{get_text, item_to_inv};
+
+// the contents of whichever tab was requested
+fn get_selected_tab_contents(browse: &Value) -> Vec<Value> {
+  let empty_vec = vec![];
+  let tabs = browse["contents"]["twoColumnBrowseResultsRenderer"]["tabs"].as_array().unwrap_or(&empty_vec);
+  match tabs.iter().find(|tab| tab["tabRenderer"]["selected"].as_bool().unwrap_or(false)) {
+    Some(tab) => {
+      let content = &tab["tabRenderer"]["content"];
+      match content["richGridRenderer"]["contents"].as_array() {
+        Some(contents) => contents.to_owned(),
+        None => content["sectionListRenderer"]["contents"][0]["itemSectionRenderer"]["contents"][0]["gridRenderer"]["items"].as_array().map(|items| items.to_owned()).unwrap_or(vec![])
+      }
+    },
+    None => vec![]
+  }
+}
+
+// channel `browse` response -> iv channel
+pub fn fmt_inv_channel(browse: &Value) -> Option<Map<String, Value>> {
+  let metadata = &browse["metadata"]["channelMetadataRenderer"];
+  let author_id = metadata["externalId"].as_str()?;
+  let description = metadata["description"].as_str().unwrap_or("");
+  let mut map = Map::<String, Value>::new();
+  map.insert(String::from("author"), json!(metadata["title"].as_str().unwrap_or("")));
+  map.insert(String::from("authorId"), json!(author_id));
+  map.insert(String::from("authorUrl"), json!(format!("/channel/{}", author_id)));
+  map.insert(String::from("authorThumbnails"), json!(metadata["avatar"]["thumbnails"].as_array().unwrap_or(&vec![])));
+  map.insert(String::from("subCountText"), json!(get_text(&browse["header"]["c4TabbedHeaderRenderer"]["subscriberCountText"]).unwrap_or(String::from(""))));
+  map.insert(String::from("isFamilyFriendly"), json!(metadata["isFamilySafe"].as_bool().unwrap_or(true)));
+  map.insert(String::from("description"), json!(description));
+  map.insert(String::from("descriptionHtml"), json!(description.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\n", "<br>")));
+  map.insert(String::from("allowedRegions"), json!(metadata["availableCountryCodes"].as_array().unwrap_or(&vec![])));
+  map.insert(String::from("latestVideos"), json!(get_selected_tab_contents(browse).iter().filter_map(item_to_inv).map(|mut video| {
+    // channel tabs don't repeat the author on every item
+    video.insert(String::from("author"), json!(metadata["title"].as_str().unwrap_or("")));
+    video.insert(String::from("authorId"), json!(author_id));
+    video.insert(String::from("authorUrl"), json!(format!("/channel/{}", author_id)));
+    video
+  }).collect::<Vec::<Map<String, Value>>>()));
+  Some(map)
+}
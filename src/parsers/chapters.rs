@@ -0,0 +1,140 @@
+use serde_json::{json, Value};
+use regex::Regex;
+use crate::parsers::renderers::{get_text, get_thumbnails, parse_length};
+
+struct Chapter {
+  title: String,
+  start_seconds: i64,
+  thumbnail: Value
+}
+
+// the chapters panel in `next` (`engagement-panel-macro-markers-description-chapters` or the auto generated one)
+fn get_panel_chapters(next: &Value) -> Vec<Chapter> {
+  let empty_vec = vec![];
+  for panel in next["engagementPanels"].as_array().unwrap_or(&empty_vec) {
+    let renderer = &panel["engagementPanelSectionListRenderer"];
+    if !renderer["panelIdentifier"].as_str().unwrap_or("").starts_with("engagement-panel-macro-markers") {
+      continue;
+    }
+    let chapters = renderer["content"]["macroMarkersListRenderer"]["contents"].as_array().unwrap_or(&empty_vec).iter().filter_map(|item| {
+      let marker = &item["macroMarkersListItemRenderer"];
+      Some(Chapter {
+        title: get_text(&marker["title"])?,
+        start_seconds: marker["onTap"]["watchEndpoint"]["startTimeSeconds"].as_i64()?,
+        thumbnail: get_thumbnails(&marker["thumbnail"])
+      })
+    }).collect::<Vec::<Chapter>>();
+    if !chapters.is_empty() {
+      return chapters;
+    }
+  }
+  vec![]
+}
+
+// youtube only turns timestamps into chapters when the first one is 0:00 and there are at least 3 of them
+fn get_description_chapters(description: &str) -> Vec<Chapter> {
+  let Ok(timestamp_re) = Regex::new(r#"(?:^|[\s(\[])((?:\d{1,2}:)?\d{1,2}:\d{2})(?:$|[\s)\]])"#) else { todo!() };
+  let chapters = description.lines().filter_map(|line| {
+    let captures = timestamp_re.captures(line)?;
+    let timestamp = captures.get(1)?;
+    let title = format!("{} {}", &line[..timestamp.start()], &line[timestamp.end()..]);
+    // ex: `0:00 - Intro`, `[0:00] Intro`, `Intro (0:00)`
+    let title = title.trim_matches(|c: char| c.is_whitespace() || "-–—|:()[]".contains(c));
+    Some(Chapter {
+      title: String::from(title),
+      start_seconds: parse_length(timestamp.as_str()),
+      thumbnail: json!([])
+    })
+  }).collect::<Vec::<Chapter>>();
+  if chapters.len() < 3 || chapters[0].start_seconds != 0 {
+    return vec![];
+  }
+  chapters
+}
+
+// iv doesn't have chapters, so they follow the rest of its schema
+pub fn get_chapters(next: &Value, player: &Value) -> Value {
+  let mut chapters = get_panel_chapters(next);
+  if chapters.is_empty() {
+    chapters = get_description_chapters(player["videoDetails"]["shortDescription"].as_str().unwrap_or(""));
+  }
+  let length_seconds = player["videoDetails"]["lengthSeconds"].as_str().and_then(|length| length.parse::<i64>().ok()).unwrap_or(0);
+  json!(chapters.iter().enumerate().map(|(i, chapter)| {
+    let end_seconds = match chapters.get(i + 1) {
+      Some(next_chapter) => next_chapter.start_seconds,
+      None => length_seconds.max(chapter.start_seconds)
+    };
+    json!({
+      "title": chapter.title,
+      "startSeconds": chapter.start_seconds,
+      "endSeconds": end_seconds,
+      "thumbnail": chapter.thumbnail
+    })
+  }).collect::<Vec::<Value>>())
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::{json, Value};
+  use super::get_chapters;
+
+  fn player(description: &str, length_seconds: &str) -> Value {
+    json!({ "videoDetails": { "shortDescription": description, "lengthSeconds": length_seconds } })
+  }
+
+  fn marker(title: &str, start_seconds: i64) -> Value {
+    json!({
+      "macroMarkersListItemRenderer": {
+        "title": { "simpleText": title },
+        "onTap": { "watchEndpoint": { "startTimeSeconds": start_seconds } },
+        "thumbnail": { "thumbnails": [{ "url": "//i.ytimg.com/vi/jNQXAC9IVRw/hqdefault_0.jpg", "width": 168, "height": 94 }] }
+      }
+    })
+  }
+
+  #[test]
+  fn parses_panel_chapters() {
+    let next = json!({
+      "engagementPanels": [
+        { "engagementPanelSectionListRenderer": { "panelIdentifier": "engagement-panel-structured-description" } },
+        { "engagementPanelSectionListRenderer": {
+          "panelIdentifier": "engagement-panel-macro-markers-description-chapters",
+          "content": { "macroMarkersListRenderer": { "contents": [marker("Intro", 0), marker("Elephants", 5), { "unknownRenderer": {} }] } }
+        } }
+      ]
+    });
+    // the panel wins over the description
+    let chapters = get_chapters(&next, &player("0:00 a\n0:01 b\n0:02 c", "19"));
+    assert_eq!(chapters, json!([
+      { "title": "Intro", "startSeconds": 0, "endSeconds": 5, "thumbnail": [{ "url": "https://i.ytimg.com/vi/jNQXAC9IVRw/hqdefault_0.jpg", "width": 168, "height": 94 }] },
+      { "title": "Elephants", "startSeconds": 5, "endSeconds": 19, "thumbnail": [{ "url": "https://i.ytimg.com/vi/jNQXAC9IVRw/hqdefault_0.jpg", "width": 168, "height": 94 }] }
+    ]));
+  }
+
+  #[test]
+  fn parses_description_chapters() {
+    let description = "Links below\n0:00 - Intro\n[1:30] The middle\nThe end (1:02:03)\nnot a chapter: 12:345";
+    let chapters = get_chapters(&json!({}), &player(description, "4000"));
+    assert_eq!(chapters, json!([
+      { "title": "Intro", "startSeconds": 0, "endSeconds": 90, "thumbnail": [] },
+      { "title": "The middle", "startSeconds": 90, "endSeconds": 3723, "thumbnail": [] },
+      { "title": "The end", "startSeconds": 3723, "endSeconds": 4000, "thumbnail": [] }
+    ]));
+  }
+
+  #[test]
+  fn ignores_descriptions_that_youtube_wouldnt_turn_into_chapters() {
+    // fewer than 3 timestamps
+    assert_eq!(get_chapters(&json!({}), &player("0:00 Intro\n1:00 Outro", "120")), json!([]));
+    // not starting at 0:00
+    assert_eq!(get_chapters(&json!({}), &player("0:10 a\n1:00 b\n2:00 c", "180")), json!([]));
+    assert_eq!(get_chapters(&json!({}), &json!({})), json!([]));
+  }
+
+  #[test]
+  fn keeps_the_last_chapter_from_ending_before_it_starts() {
+    // `lengthSeconds` is missing for live streams
+    let chapters = get_chapters(&json!({}), &json!({ "videoDetails": { "shortDescription": "0:00 a\n0:10 b\n0:20 c" } }));
+    assert_eq!(chapters[2]["endSeconds"], json!(20));
+  }
+}
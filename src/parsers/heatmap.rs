@@ -0,0 +1,104 @@
+use serde_json::{json, Value};
+
+// innertube sends these as either numbers or strings
+fn get_number(value: &Value) -> Option<f64> {
+  value.as_f64().or(value.as_str().and_then(|value| value.parse::<f64>().ok()))
+}
+
+fn heat_marker_to_inv(start_millis: &Value, duration_millis: &Value, intensity: &Value) -> Option<Value> {
+  Some(json!({
+    "startMillis": get_number(start_millis)? as i64,
+    "durationMillis": get_number(duration_millis)? as i64,
+    "intensity": get_number(intensity)?.clamp(0.0, 1.0)
+  }))
+}
+
+// current responses: `frameworkUpdates.entityBatchUpdate.mutations[].payload.macroMarkersListEntity`
+fn get_entity_heatmap(next: &Value) -> Vec<Value> {
+  let empty_vec = vec![];
+  for mutation in next["frameworkUpdates"]["entityBatchUpdate"]["mutations"].as_array().unwrap_or(&empty_vec) {
+    let markers_list = &mutation["payload"]["macroMarkersListEntity"]["markersList"];
+    if markers_list["markerType"].as_str() != Some("MARKER_TYPE_HEATMAP") {
+      continue;
+    }
+    return markers_list["markers"].as_array().unwrap_or(&empty_vec).iter().filter_map(|marker| {
+      heat_marker_to_inv(&marker["startMillis"], &marker["durationMillis"], &marker["intensityScoreNormalized"])
+    }).collect();
+  }
+  vec![]
+}
+
+// older responses keep it in the player bar's `markersMap`
+fn get_player_bar_heatmap(next: &Value) -> Vec<Value> {
+  let empty_vec = vec![];
+  let player_bar = &next["playerOverlays"]["playerOverlayRenderer"]["decoratedPlayerBarRenderer"]["decoratedPlayerBarRenderer"]["playerBar"];
+  for marker_map in player_bar["multiMarkersPlayerBarRenderer"]["markersMap"].as_array().unwrap_or(&empty_vec) {
+    let heat_markers = &marker_map["value"]["heatmap"]["heatmapRenderer"]["heatMarkers"];
+    if !heat_markers.is_array() {
+      continue;
+    }
+    return heat_markers.as_array().unwrap_or(&empty_vec).iter().filter_map(|heat_marker| {
+      let renderer = &heat_marker["heatMarkerRenderer"];
+      heat_marker_to_inv(&renderer["timeRangeStartMillis"], &renderer["markerDurationMillis"], &renderer["heatMarkerIntensityScoreNormalized"])
+    }).collect();
+  }
+  vec![]
+}
+
+// "most replayed" markers as `{ startMillis, durationMillis, intensity }` with intensity between 0 and 1
+pub fn get_heatmap(next: &Value) -> Value {
+  let heatmap = get_entity_heatmap(next);
+  if heatmap.is_empty() {
+    json!(get_player_bar_heatmap(next))
+  } else {
+    json!(heatmap)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+  use super::get_heatmap;
+
+  #[test]
+  fn parses_entity_heatmap() {
+    let next = json!({
+      "frameworkUpdates": { "entityBatchUpdate": { "mutations": [
+        { "payload": { "macroMarkersListEntity": { "markersList": { "markerType": "MARKER_TYPE_TIMESTAMPS", "markers": [{ "startMillis": "0", "durationMillis": "1000", "intensityScoreNormalized": 0.5 }] } } } },
+        { "payload": { "macroMarkersListEntity": { "markersList": { "markerType": "MARKER_TYPE_HEATMAP", "markers": [
+          { "startMillis": "0", "durationMillis": "190", "intensityScoreNormalized": 1 },
+          { "startMillis": "190", "durationMillis": "190", "intensityScoreNormalized": "0.25" },
+          { "startMillis": "380", "durationMillis": "190", "intensityScoreNormalized": 1.5 },
+          { "startMillis": "570" }
+        ] } } } }
+      ] } }
+    });
+    assert_eq!(get_heatmap(&next), json!([
+      { "startMillis": 0, "durationMillis": 190, "intensity": 1.0 },
+      { "startMillis": 190, "durationMillis": 190, "intensity": 0.25 },
+      { "startMillis": 380, "durationMillis": 190, "intensity": 1.0 }
+    ]));
+  }
+
+  #[test]
+  fn falls_back_to_the_player_bar_heatmap() {
+    let next = json!({
+      "playerOverlays": { "playerOverlayRenderer": { "decoratedPlayerBarRenderer": { "decoratedPlayerBarRenderer": { "playerBar": { "multiMarkersPlayerBarRenderer": { "markersMap": [
+        { "key": "DESCRIPTION_CHAPTERS", "value": { "chapters": [] } },
+        { "key": "HEATSEEKER", "value": { "heatmap": { "heatmapRenderer": { "heatMarkers": [
+          { "heatMarkerRenderer": { "timeRangeStartMillis": 0, "markerDurationMillis": 2500, "heatMarkerIntensityScoreNormalized": 0.75 } },
+          { "heatMarkerRenderer": { "timeRangeStartMillis": 2500, "markerDurationMillis": 2500, "heatMarkerIntensityScoreNormalized": -0.1 } }
+        ] } } } }
+      ] } } } } } }
+    });
+    assert_eq!(get_heatmap(&next), json!([
+      { "startMillis": 0, "durationMillis": 2500, "intensity": 0.75 },
+      { "startMillis": 2500, "durationMillis": 2500, "intensity": 0.0 }
+    ]));
+  }
+
+  #[test]
+  fn is_empty_without_a_heatmap() {
+    assert_eq!(get_heatmap(&json!({})), json!([]));
+  }
+}
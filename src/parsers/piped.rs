@@ -0,0 +1,210 @@
+use serde_json::{json, Value, Map};
+use chrono::{TimeZone, Utc};
+
+// iv has a mix of numbers and numeric strings
+fn get_i64(value: &Value) -> Option<i64> {
+  value.as_i64().or(value.as_str().and_then(|value| value.parse::<i64>().ok()))
+}
+
+fn get_str<'a>(map: &'a Map<String, Value>, key: &str) -> &'a str {
+  map.get(key).and_then(|value| value.as_str()).unwrap_or("")
+}
+
+fn get_avatar(map: &Map<String, Value>) -> Value {
+  map.get("authorThumbnails").and_then(|thumbnails| thumbnails.as_array()).and_then(|thumbnails| thumbnails.last()).map(|thumbnail| thumbnail["url"].clone()).unwrap_or(Value::Null)
+}
+
+// ex: `0-740` -> (0, 740)
+fn parse_range(range: &Value) -> (i64, i64) {
+  match range.as_str().and_then(|range| range.split_once('-')) {
+    Some((start, end)) => (start.parse::<i64>().unwrap_or(0), end.parse::<i64>().unwrap_or(0)),
+    None => (0, 0)
+  }
+}
+
+// iv video (search result, recommendation, playlist or channel video) -> piped `StreamItem`
+pub fn stream_item_to_piped(video: &Map<String, Value>, base_url: &str) -> Value {
+  let video_id = get_str(video, "videoId");
+  let published = video.get("published").and_then(get_i64);
+  let is_live = video.get("liveNow").and_then(|live_now| live_now.as_bool()).unwrap_or(false);
+  json!({
+    "url": format!("/watch?v={}", video_id),
+    "type": "stream",
+    "title": get_str(video, "title"),
+    "thumbnail": format!("{}/vi/{}/hqdefault.jpg", base_url, video_id),
+    "uploaderName": get_str(video, "author"),
+    "uploaderUrl": format!("/channel/{}", get_str(video, "authorId")),
+    "uploaderAvatar": get_avatar(video),
+    "uploadedDate": video.get("publishedText").filter(|published_text| published_text.as_str().map(|text| !text.is_empty()).unwrap_or(false)),
+    "shortDescription": video.get("description"),
+    "duration": if is_live { -1 } else { video.get("lengthSeconds").and_then(get_i64).unwrap_or(-1) },
+    "views": video.get("viewCount").and_then(get_i64).unwrap_or(-1),
+    "uploaded": published.map(|published| published * 1000).unwrap_or(-1),
+    "uploaderVerified": false,
+    "isShort": video.get("isShort").and_then(|is_short| is_short.as_bool()).unwrap_or(false)
+  })
+}
+
+// iv search result -> piped search item
+pub fn search_item_to_piped(item: &Map<String, Value>, base_url: &str) -> Option<Value> {
+  match get_str(item, "type") {
+    "video" => Some(stream_item_to_piped(item, base_url)),
+    "channel" => Some(json!({
+      "url": format!("/channel/{}", get_str(item, "authorId")),
+      "type": "channel",
+      "name": get_str(item, "author"),
+      "thumbnail": get_avatar(item),
+      "description": item.get("description"),
+      "subscribers": -1,
+      "videos": -1,
+      "verified": false
+    })),
+    "playlist" => Some(json!({
+      "url": format!("/playlist?list={}", get_str(item, "playlistId")),
+      "type": "playlist",
+      "name": get_str(item, "title"),
+      "thumbnail": get_str(item, "playlistThumbnail"),
+      "uploaderName": get_str(item, "author"),
+      "uploaderUrl": format!("/channel/{}", get_str(item, "authorId")),
+      "uploaderVerified": false,
+      "playlistType": "NORMAL",
+      "videos": item.get("videoCount").and_then(get_i64).unwrap_or(-1)
+    })),
+    _ => None
+  }
+}
+
+// ex: `video/webm; codecs="vp9"` -> piped's `WEBM`
+fn get_piped_format(mime_type: &str) -> &str {
+  match mime_type {
+    "video/mp4" => "MPEG_4",
+    "video/webm" => "WEBM",
+    "video/3gpp" => "v3GPP",
+    "audio/mp4" => "M4A",
+    "audio/webm" => "WEBMA_OPUS",
+    _ => ""
+  }
+}
+
+// iv `formatStreams` or `adaptiveFormats` entry -> piped stream
+fn format_to_piped(format: &Value, video_only: bool) -> Value {
+  let format_type = format["type"].as_str().unwrap_or("");
+  let mime_type = format_type.split(';').next().unwrap_or("").trim();
+  let codec = format_type.split("codecs=\"").nth(1).map(|codecs| codecs.trim_end_matches('"')).unwrap_or("");
+  let bitrate = get_i64(&format["bitrate"]).unwrap_or(0);
+  let is_audio = mime_type.starts_with("audio/");
+  let (init_start, init_end) = parse_range(&format["init"]);
+  let (index_start, index_end) = parse_range(&format["index"]);
+  // `size` is `1920x1080` on adaptive formats
+  let (width, height) = match format["size"].as_str().and_then(|size| size.split_once('x')) {
+    Some((width, height)) => (width.parse::<i64>().unwrap_or(0), height.parse::<i64>().unwrap_or(0)),
+    None => (0, 0)
+  };
+  json!({
+    "url": format["url"],
+    "format": get_piped_format(mime_type),
+    "quality": if is_audio { format!("{} kbps", bitrate / 1000) } else { String::from(format["qualityLabel"].as_str().unwrap_or("")) },
+    "mimeType": mime_type,
+    "codec": codec,
+    "audioTrackId": null,
+    "audioTrackName": null,
+    "audioTrackType": null,
+    "audioTrackLocale": null,
+    "videoOnly": video_only,
+    "itag": get_i64(&format["itag"]).unwrap_or(0),
+    "bitrate": bitrate,
+    "initStart": init_start,
+    "initEnd": init_end,
+    "indexStart": index_start,
+    "indexEnd": index_end,
+    "width": width,
+    "height": height,
+    "fps": get_i64(&format["fps"]).unwrap_or(0),
+    "contentLength": get_i64(&format["clen"]).unwrap_or(-1)
+  })
+}
+
+// iv video -> piped `/streams/{video_id}`
+pub fn streams_to_piped(video: &Map<String, Value>, chapters: &Value, base_url: &str) -> Value {
+  let empty_vec = vec![];
+  let video_id = get_str(video, "videoId");
+  let adaptive_formats = video.get("adaptiveFormats").and_then(|formats| formats.as_array()).unwrap_or(&empty_vec);
+  let audio_streams = adaptive_formats.iter().filter(|format| format["type"].as_str().unwrap_or("").starts_with("audio/")).map(|format| format_to_piped(format, false)).collect::<Vec::<Value>>();
+  let mut video_streams = adaptive_formats.iter().filter(|format| format["type"].as_str().unwrap_or("").starts_with("video/")).map(|format| format_to_piped(format, true)).collect::<Vec::<Value>>();
+  video_streams.extend(video.get("formatStreams").and_then(|formats| formats.as_array()).unwrap_or(&empty_vec).iter().map(|format| format_to_piped(format, false)));
+  let related_streams = video.get("recommendedVideos").and_then(|videos| videos.as_array()).unwrap_or(&empty_vec).iter().filter_map(|video| video.as_object()).map(|video| stream_item_to_piped(video, base_url)).collect::<Vec::<Value>>();
+  let upload_date = video.get("published").and_then(get_i64).and_then(|published| Utc.timestamp_opt(published, 0).single()).map(|published| published.to_rfc3339());
+  json!({
+    "title": get_str(video, "title"),
+    "description": video.get("descriptionHtml").or(video.get("description")),
+    "uploadDate": upload_date,
+    "uploader": get_str(video, "author"),
+    "uploaderUrl": format!("/channel/{}", get_str(video, "authorId")),
+    "uploaderAvatar": get_avatar(video),
+    "thumbnailUrl": format!("{}/vi/{}/maxresdefault.jpg", base_url, video_id),
+    "hls": video.get("hlsUrl"),
+    "dash": null,
+    "lbryId": null,
+    "category": video.get("genre"),
+    "license": "YouTube licence",
+    "visibility": if video.get("isListed").and_then(|is_listed| is_listed.as_bool()).unwrap_or(true) { "public" } else { "unlisted" },
+    "tags": video.get("keywords").unwrap_or(&json!([])),
+    "metaInfo": [],
+    "uploaderVerified": false,
+    "duration": video.get("lengthSeconds").and_then(get_i64).unwrap_or(0),
+    "views": video.get("viewCount").and_then(get_i64).unwrap_or(0),
+    "likes": video.get("likeCount").and_then(get_i64).unwrap_or(-1),
+    "dislikes": -1,
+    "uploaderSubscriberCount": -1,
+    "audioStreams": audio_streams,
+    "videoStreams": video_streams,
+    "relatedStreams": related_streams,
+    "subtitles": [],
+    "livestream": video.get("liveNow").and_then(|live_now| live_now.as_bool()).unwrap_or(false),
+    "proxyUrl": base_url,
+    "chapters": chapters.as_array().unwrap_or(&empty_vec).iter().map(|chapter| json!({
+      "title": chapter["title"],
+      "image": chapter["thumbnail"].as_array().and_then(|thumbnails| thumbnails.last()).map(|thumbnail| thumbnail["url"].clone()).unwrap_or(Value::Null),
+      "start": chapter["startSeconds"]
+    })).collect::<Vec::<Value>>(),
+    "previewFrames": []
+  })
+}
+
+// the videos of an iv playlist page as piped `StreamItem`s
+pub fn playlist_videos_to_piped(playlist: &Map<String, Value>, base_url: &str) -> Vec<Value> {
+  playlist.get("videos").and_then(|videos| videos.as_array()).unwrap_or(&vec![]).iter().filter_map(|video| video.as_object()).map(|video| stream_item_to_piped(video, base_url)).collect()
+}
+
+// iv playlist -> piped `/playlists/{playlist_id}`
+pub fn playlist_to_piped(playlist: &Map<String, Value>, nextpage: Option<String>, base_url: &str) -> Value {
+  let first_video_id = playlist.get("videos").and_then(|videos| videos[0]["videoId"].as_str()).unwrap_or("");
+  json!({
+    "name": get_str(playlist, "title"),
+    "thumbnailUrl": format!("{}/vi/{}/hqdefault.jpg", base_url, first_video_id),
+    "description": get_str(playlist, "description"),
+    "bannerUrl": null,
+    "nextpage": nextpage,
+    "uploader": get_str(playlist, "author"),
+    "uploaderUrl": format!("/channel/{}", get_str(playlist, "authorId")),
+    "uploaderAvatar": get_avatar(playlist),
+    "videos": playlist.get("videoCount").and_then(get_i64).unwrap_or(-1),
+    "relatedStreams": playlist_videos_to_piped(playlist, base_url)
+  })
+}
+
+// iv channel -> piped `/channel/{channel_id}`
+pub fn channel_to_piped(channel: &Map<String, Value>, base_url: &str) -> Value {
+  json!({
+    "id": get_str(channel, "authorId"),
+    "name": get_str(channel, "author"),
+    "avatarUrl": get_avatar(channel),
+    "bannerUrl": null,
+    "description": get_str(channel, "description"),
+    "nextpage": null,
+    "subscriberCount": -1,
+    "verified": false,
+    "relatedStreams": channel.get("latestVideos").and_then(|videos| videos.as_array()).unwrap_or(&vec![]).iter().filter_map(|video| video.as_object()).map(|video| stream_item_to_piped(video, base_url)).collect::<Vec::<Value>>(),
+    "tabs": []
+  })
+}
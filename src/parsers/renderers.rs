@@ -0,0 +1,117 @@
+use serde_json::{json, Value, Map};
+use yayti::helpers::generate_yt_video_thumbnails_within_max_size;
+use crate::parsers::shorts::{is_short_renderer, reel_item_renderer_to_inv, shorts_lockup_to_inv};
+
+// text in innertube is either `{ "simpleText": "..." }` or `{ "runs": [{ "text": "..." }, ...] }`
+pub fn get_text(value: &Value) -> Option<String> {
+  match value["simpleText"].as_str() {
+    Some(text) => Some(String::from(text)),
+    None => value["runs"].as_array().map(|runs| {
+      runs.iter().map(|run| run["text"].as_str().unwrap_or("")).collect::<Vec::<&str>>().join("")
+    })
+  }
+}
+
+// ex: `1,234,567 views` -> 1234567
+pub fn parse_number(text: &str) -> i64 {
+  text.chars().filter(|c| c.is_ascii_digit()).collect::<String>().parse::<i64>().unwrap_or(0)
+}
+
+// ex: `1:02:03` -> 3723
+pub fn parse_length(text: &str) -> i64 {
+  text.split(':').fold(0, |seconds, part| seconds * 60 + part.trim().parse::<i64>().unwrap_or(0))
+}
+
+pub fn get_thumbnails(value: &Value) -> Value {
+  json!(value["thumbnails"].as_array().unwrap_or(&vec![]).iter().map(|thumbnail| {
+    let url = thumbnail["url"].as_str().unwrap_or("");
+    json!({
+      "url": if url.starts_with("//") { format!("https:{}", url) } else { String::from(url) },
+      "width": thumbnail["width"],
+      "height": thumbnail["height"]
+    })
+  }).collect::<Vec::<Value>>())
+}
+
+// `videoRenderer` -> iv search result
+pub fn video_renderer_to_inv(renderer: &Value) -> Option<Map<String, Value>> {
+  let video_id = renderer["videoId"].as_str()?;
+  let owner = if renderer["ownerText"].is_object() { &renderer["ownerText"] } else { &renderer["longBylineText"] };
+  let author_id = owner["runs"][0]["navigationEndpoint"]["browseEndpoint"]["browseId"].as_str().unwrap_or("");
+  let view_count_text = get_text(&renderer["viewCountText"]).unwrap_or(String::from(""));
+  let badges = renderer["badges"].as_array().map(|badges| {
+    badges.iter().filter_map(|badge| badge["metadataBadgeRenderer"]["style"].as_str()).collect::<Vec::<&str>>()
+  }).unwrap_or(vec![]);
+  let mut map = Map::<String, Value>::new();
+  map.insert(String::from("type"), json!("video"));
+  map.insert(String::from("title"), json!(get_text(&renderer["title"]).unwrap_or(String::from(""))));
+  map.insert(String::from("videoId"), json!(video_id));
+  map.insert(String::from("author"), json!(get_text(owner).unwrap_or(String::from(""))));
+  map.insert(String::from("authorId"), json!(author_id));
+  map.insert(String::from("authorUrl"), json!(format!("/channel/{}", author_id)));
+  map.insert(String::from("videoThumbnails"), json!(generate_yt_video_thumbnails_within_max_size(video_id, 480)));
+  map.insert(String::from("description"), json!(get_text(&renderer["detailedMetadataSnippets"][0]["snippetText"]).or(get_text(&renderer["descriptionSnippet"])).unwrap_or(String::from(""))));
+  map.insert(String::from("viewCount"), json!(parse_number(&view_count_text)));
+  map.insert(String::from("viewCountText"), json!(view_count_text));
+  map.insert(String::from("publishedText"), json!(get_text(&renderer["publishedTimeText"]).unwrap_or(String::from(""))));
+  map.insert(String::from("lengthSeconds"), json!(get_text(&renderer["lengthText"]).map(|length| parse_length(&length)).unwrap_or(0)));
+  map.insert(String::from("liveNow"), json!(badges.contains(&"BADGE_STYLE_TYPE_LIVE_NOW")));
+  map.insert(String::from("premium"), json!(badges.contains(&"BADGE_STYLE_TYPE_MEMBERS_ONLY")));
+  map.insert(String::from("isUpcoming"), json!(renderer["upcomingEventData"].is_object()));
+  map.insert(String::from("isShort"), json!(is_short_renderer(renderer)));
+  Some(map)
+}
+
+// `channelRenderer` -> iv search result
+pub fn channel_renderer_to_inv(renderer: &Value) -> Option<Map<String, Value>> {
+  let author_id = renderer["channelId"].as_str()?;
+  let mut map = Map::<String, Value>::new();
+  map.insert(String::from("type"), json!("channel"));
+  map.insert(String::from("author"), json!(get_text(&renderer["title"]).unwrap_or(String::from(""))));
+  map.insert(String::from("authorId"), json!(author_id));
+  map.insert(String::from("authorUrl"), json!(format!("/channel/{}", author_id)));
+  map.insert(String::from("authorThumbnails"), get_thumbnails(&renderer["thumbnail"]));
+  map.insert(String::from("subCountText"), json!(get_text(&renderer["subscriberCountText"]).unwrap_or(String::from(""))));
+  map.insert(String::from("description"), json!(get_text(&renderer["descriptionSnippet"]).unwrap_or(String::from(""))));
+  Some(map)
+}
+
+// `playlistRenderer` -> iv search result
+pub fn playlist_renderer_to_inv(renderer: &Value) -> Option<Map<String, Value>> {
+  let playlist_id = renderer["playlistId"].as_str()?;
+  let author_id = renderer["longBylineText"]["runs"][0]["navigationEndpoint"]["browseEndpoint"]["browseId"].as_str().unwrap_or("");
+  let mut map = Map::<String, Value>::new();
+  map.insert(String::from("type"), json!("playlist"));
+  map.insert(String::from("title"), json!(get_text(&renderer["title"]).unwrap_or(String::from(""))));
+  map.insert(String::from("playlistId"), json!(playlist_id));
+  map.insert(String::from("playlistThumbnail"), json!(renderer["thumbnails"][0]["thumbnails"][0]["url"].as_str().unwrap_or("")));
+  map.insert(String::from("author"), json!(get_text(&renderer["longBylineText"]).unwrap_or(String::from(""))));
+  map.insert(String::from("authorId"), json!(author_id));
+  map.insert(String::from("authorUrl"), json!(format!("/channel/{}", author_id)));
+  map.insert(String::from("videoCount"), json!(renderer["videoCount"].as_str().map(parse_number).unwrap_or(0)));
+  Some(map)
+}
+
+// any renderer we know how to turn into an iv item
+pub fn item_to_inv(item: &Value) -> Option<Map<String, Value>> {
+  if item["videoRenderer"].is_object() {
+    video_renderer_to_inv(&item["videoRenderer"])
+  } else if item["channelRenderer"].is_object() {
+    channel_renderer_to_inv(&item["channelRenderer"])
+  } else if item["playlistRenderer"].is_object() {
+    playlist_renderer_to_inv(&item["playlistRenderer"])
+  } else if item["reelItemRenderer"].is_object() {
+    reel_item_renderer_to_inv(&item["reelItemRenderer"])
+  } else if item["shortsLockupViewModel"].is_object() {
+    shorts_lockup_to_inv(&item["shortsLockupViewModel"])
+  } else if item["richItemRenderer"].is_object() {
+    item_to_inv(&item["richItemRenderer"]["content"])
+  } else {
+    None
+  }
+}
+
+// the token for the next page in a list of items
+pub fn get_continuation_token(items: &[Value]) -> Option<String> {
+  items.iter().find_map(|item| item["continuationItemRenderer"]["continuationEndpoint"]["continuationCommand"]["token"].as_str().map(String::from))
+}
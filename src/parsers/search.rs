@@ -0,0 +1,26 @@
+use serde_json::{Value, Map};
+use crate::parsers::renderers::{item_to_inv, get_continuation_token};
+
+// search results come back in sections on the first page and in `appendContinuationItemsAction` on later pages
+fn get_sections(search: &Value) -> Vec<Value> {
+  match search["contents"]["twoColumnSearchResultsRenderer"]["primaryContents"]["sectionListRenderer"]["contents"].as_array() {
+    Some(sections) => sections.to_owned(),
+    None => search["onResponseReceivedCommands"][0]["appendContinuationItemsAction"]["continuationItems"].as_array().map(|sections| sections.to_owned()).unwrap_or(vec![])
+  }
+}
+
+pub fn get_search_results(search: &Value) -> Vec<Map<String, Value>> {
+  get_sections(search).iter().flat_map(|section| {
+    section["itemSectionRenderer"]["contents"].as_array().map(|items| items.to_owned()).unwrap_or(vec![])
+  }).flat_map(|item| {
+    // shorts are grouped into shelves between the other results
+    match item["reelShelfRenderer"]["items"].as_array() {
+      Some(items) => items.to_owned(),
+      None => vec![item]
+    }
+  }).filter_map(|item| item_to_inv(&item)).collect()
+}
+
+pub fn get_search_continuation(search: &Value) -> Option<String> {
+  get_continuation_token(&get_sections(search))
+}
@@ -0,0 +1,62 @@
+use serde_json::{json, Value, Map};
+use yayti::helpers::generate_yt_video_thumbnails_within_max_size;
+use crate::parsers::renderers::{get_text, parse_number};
+
+// shorts can be up to 3 minutes long
+const MAX_SHORT_LENGTH: i64 = 180;
+
+// a player response is a short when it's vertical and short enough, since `player` has no reel marker of its own
+pub fn is_short_player(player: &Value) -> bool {
+  let length_seconds = player["videoDetails"]["lengthSeconds"].as_str().and_then(|length| length.parse::<i64>().ok()).unwrap_or(0);
+  let empty_vec = vec![];
+  let is_vertical = player["streamingData"]["adaptiveFormats"].as_array().unwrap_or(&empty_vec).iter()
+    .find(|format| format["width"].is_number() && format["height"].is_number())
+    .map(|format| format["height"].as_i64().unwrap_or(0) > format["width"].as_i64().unwrap_or(0))
+    .unwrap_or(false);
+  is_vertical && length_seconds > 0 && length_seconds <= MAX_SHORT_LENGTH
+}
+
+// renderers that open the shorts player link to a `reelWatchEndpoint`
+pub fn is_short_renderer(renderer: &Value) -> bool {
+  renderer["navigationEndpoint"]["reelWatchEndpoint"].is_object()
+}
+
+fn short_to_inv(video_id: &str, title: String, view_count_text: String) -> Map<String, Value> {
+  let mut map = Map::<String, Value>::new();
+  map.insert(String::from("type"), json!("video"));
+  map.insert(String::from("title"), json!(title));
+  map.insert(String::from("videoId"), json!(video_id));
+  map.insert(String::from("videoThumbnails"), json!(generate_yt_video_thumbnails_within_max_size(video_id, 480)));
+  map.insert(String::from("viewCount"), json!(parse_number(&view_count_text)));
+  map.insert(String::from("viewCountText"), json!(view_count_text));
+  map.insert(String::from("isShort"), json!(true));
+  map
+}
+
+// `reelItemRenderer` -> iv search result
+pub fn reel_item_renderer_to_inv(renderer: &Value) -> Option<Map<String, Value>> {
+  let video_id = renderer["videoId"].as_str()?;
+  Some(short_to_inv(video_id, get_text(&renderer["headline"]).unwrap_or(String::from("")), get_text(&renderer["viewCountText"]).unwrap_or(String::from(""))))
+}
+
+// `shortsLockupViewModel` (the newer reel shelf items) -> iv search result
+pub fn shorts_lockup_to_inv(view_model: &Value) -> Option<Map<String, Value>> {
+  let video_id = view_model["onTap"]["innertubeCommand"]["reelWatchEndpoint"]["videoId"].as_str()?;
+  let metadata = &view_model["overlayMetadata"];
+  Some(short_to_inv(video_id, String::from(metadata["primaryText"]["content"].as_str().unwrap_or("")), String::from(metadata["secondaryText"]["content"].as_str().unwrap_or(""))))
+}
+
+// `reel/reel_watch_sequence` -> the next shorts in the feed
+pub fn get_sequence(sequence: &Value) -> Vec<Value> {
+  sequence["entries"].as_array().unwrap_or(&vec![]).iter().filter_map(|entry| {
+    let video_id = entry["command"]["reelWatchEndpoint"]["videoId"].as_str()?;
+    Some(json!({
+      "videoId": video_id,
+      "videoThumbnails": generate_yt_video_thumbnails_within_max_size(video_id, 480)
+    }))
+  }).collect()
+}
+
+pub fn get_sequence_continuation(sequence: &Value) -> Option<String> {
+  sequence["continuationEndpoint"]["continuationCommand"]["token"].as_str().map(String::from)
+}
@@ -0,0 +1,214 @@
+use std::time::{Duration, Instant};
+use actix_web::HttpRequest;
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::{from_str, Value};
+use urlencoding::encode;
+use crate::helpers::{get_previous_data, is_expired, DbWrapper};
+use crate::settings::AppSettings;
+
+// the innertube responses peers can share (not `player`, since its stream urls only work from the peer's ip and point at the peer)
+pub const SHARED_COLLECTIONS: [&str; 2] = ["next", "playlist"];
+
+// `--peer-secret` is sent in this header
+pub const PEER_SECRET_HEADER: &str = "X-Peer-Secret";
+
+// how far ahead of this instance's clock a peer's `timestamp` can be
+const MAX_PEER_CLOCK_SKEW: i64 = 60;
+
+// compares every byte, so how long it takes doesn't give away how much of the secret was right
+pub fn is_peer_request(req: &HttpRequest, app_settings: &AppSettings) -> bool {
+  let Some(peer_secret) = &app_settings.peer_secret else { return false };
+  match req.headers().get(PEER_SECRET_HEADER) {
+    Some(secret) => secret.len() == peer_secret.len() && secret.as_bytes().iter().zip(peer_secret.as_bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0,
+    None => false
+  }
+}
+
+// a fresh innertube response for the requested key (peers could be misconfigured, or running an older version), since it's cached and served as if it came from here
+fn is_valid_peer_data(collection: &str, key: &str, json: &Value, now: i64, cache_timeout: u64) -> bool {
+  let Some(timestamp) = json["timestamp"].as_i64() else { return false };
+  if timestamp > now + MAX_PEER_CLOCK_SKEW || is_expired(json, now, cache_timeout) || json["stale"].as_bool().unwrap_or(false) {
+    return false;
+  }
+  // `next` keys start with the video id
+  let is_for_video = |video_id: &Value| video_id.as_str().map(|video_id| key.starts_with(&format!("{}-", video_id))).unwrap_or(false);
+  match collection {
+    "next" => json["contents"].is_object() && is_for_video(&json["currentVideoEndpoint"]["watchEndpoint"]["videoId"]),
+    // playlists and their continuations
+    "playlist" => json["contents"].is_object() || json["onResponseReceivedActions"].is_array() || json["continuationContents"].is_object(),
+    _ => false
+  }
+}
+
+fn record_peer_request(app_settings: &AppSettings, url: &str, is_failure: bool) {
+  let mut peers = app_settings.state.peers.lock().unwrap();
+  if let Some(peer) = peers.iter_mut().find(|peer| peer.url == url) {
+    peer.record(is_failure);
+  }
+}
+
+// asks each healthy peer for its cached entry, in the order they were configured
+async fn fetch_from_peers(collection: &str, key: &str, app_settings: &AppSettings) -> Option<Value> {
+  if !SHARED_COLLECTIONS.contains(&collection) {
+    return None;
+  }
+  let Some(peer_secret) = &app_settings.peer_secret else { return None };
+  let healthy_peers = app_settings.state.peers.lock().unwrap().iter().filter(|peer| peer.healthy).map(|peer| String::from(&peer.url)).collect::<Vec::<String>>();
+  if healthy_peers.is_empty() {
+    return None;
+  }
+  let http = &app_settings.state.http;
+  for peer in healthy_peers {
+    // a slow peer shouldn't be slower than going upstream
+    match http.client.get(format!("{}/api/v1/cache/{}/{}", peer, collection, encode(key))).header(PEER_SECRET_HEADER, peer_secret).timeout(Duration::from_secs(2)).send().await {
+      Ok(res) => {
+        record_peer_request(app_settings, &peer, false);
+        if !res.status().is_success() {
+          continue;
+        }
+        match res.text().await.map(|text| from_str::<Value>(&text)) {
+          Ok(Ok(json)) if is_valid_peer_data(collection, key, &json, Utc::now().timestamp(), app_settings.cache_timeout) => return Some(json),
+          Ok(Ok(_)) => {
+            log::warn!("Ignoring peer {}'s `{}` for {}, it isn't a fresh innertube response", peer, collection, key);
+            continue;
+          },
+          _ => continue
+        }
+      },
+      Err(error) => {
+        log::warn!("Failed to reach peer {}: {}", peer, error);
+        record_peer_request(app_settings, &peer, true);
+      }
+    }
+  }
+  None
+}
+
+// the local cache first, and then the peers' caches (which are copied into the local one)
+pub async fn get_previous_data_or_from_peers(collection: &str, key: &str, db: &DbWrapper, app_settings: &AppSettings) -> Option<Value> {
+  match get_previous_data(collection, key, db, app_settings).await {
+    Some(previous_data) => Some(previous_data),
+    None => {
+      let peer_data = fetch_from_peers(collection, key, app_settings).await?;
+      if app_settings.cache_requests {
+        db.insert_json(collection, key, &peer_data).await;
+      }
+      Some(peer_data)
+    }
+  }
+}
+
+async fn check_peer(client: &Client, url: &str) -> Option<u64> {
+  let started = Instant::now();
+  match client.get(format!("{}/api/v1/stats", url)).timeout(Duration::from_secs(5)).send().await {
+    Ok(res) if res.status().is_success() => Some(started.elapsed().as_millis() as u64),
+    Ok(res) => {
+      log::warn!("Peer {} failed its health check with {}", url, res.status());
+      None
+    },
+    Err(error) => {
+      log::warn!("Peer {} failed its health check: {}", url, error);
+      None
+    }
+  }
+}
+
+// checks every peer's `/api/v1/stats` every `--peer-check-interval` seconds
+pub fn spawn_health_checks(app_settings: AppSettings) {
+  if app_settings.peers.is_empty() {
+    return;
+  }
+  if app_settings.peer_secret.is_none() {
+    log::warn!("Peers are only used with a `--peer-secret`");
+  }
+  tokio::spawn(async move {
+    loop {
+      for url in &app_settings.peers {
+        let latency_ms = check_peer(&app_settings.state.http.client, url).await;
+        let mut peers = app_settings.state.peers.lock().unwrap();
+        if let Some(peer) = peers.iter_mut().find(|peer| &peer.url == url) {
+          peer.healthy = latency_ms.is_some();
+          peer.latency_ms = latency_ms;
+          peer.last_checked = Some(Utc::now().timestamp());
+          peer.record(latency_ms.is_none());
+        }
+      }
+      tokio::time::sleep(Duration::from_secs(app_settings.peer_check_interval)).await;
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::test::TestRequest;
+  use serde_json::json;
+  use crate::settings::AppSettings;
+  use super::{is_peer_request, is_valid_peer_data, PEER_SECRET_HEADER, SHARED_COLLECTIONS};
+
+  const NOW: i64 = 1_700_000_000;
+
+  fn peer_settings(peer_secret: Option<&str>) -> AppSettings {
+    let mut args = vec![String::from("yaytapi"), String::from("--peer=https://peer.example.com")];
+    if let Some(peer_secret) = peer_secret {
+      args.push(format!("--peer-secret={}", peer_secret));
+    }
+    AppSettings::from_cli_args(&args)
+  }
+
+  #[test]
+  fn only_accepts_requests_with_the_peer_secret() {
+    let app_settings = peer_settings(Some("hunter2"));
+    assert!(is_peer_request(&TestRequest::default().insert_header((PEER_SECRET_HEADER, "hunter2")).to_http_request(), &app_settings));
+    assert!(!is_peer_request(&TestRequest::default().insert_header((PEER_SECRET_HEADER, "hunter3")).to_http_request(), &app_settings));
+    assert!(!is_peer_request(&TestRequest::default().insert_header((PEER_SECRET_HEADER, "hunter")).to_http_request(), &app_settings));
+    assert!(!is_peer_request(&TestRequest::default().to_http_request(), &app_settings));
+    // without a secret of its own, nothing gets in
+    assert!(!is_peer_request(&TestRequest::default().insert_header((PEER_SECRET_HEADER, "")).to_http_request(), &peer_settings(None)));
+  }
+
+  #[test]
+  fn accepts_fresh_responses_for_the_requested_key() {
+    let next = json!({ "contents": {}, "currentVideoEndpoint": { "watchEndpoint": { "videoId": "jNQXAC9IVRw" } }, "timestamp": NOW - 10 });
+    assert!(is_valid_peer_data("next", "jNQXAC9IVRw-en-US", &next, NOW, 3600));
+    let continuation = json!({ "onResponseReceivedActions": [], "timestamp": NOW });
+    assert!(is_valid_peer_data("playlist", "4qmFsgI-en-US", &continuation, NOW, 3600));
+  }
+
+  #[test]
+  fn rejects_player_responses() {
+    // stream urls only work from the peer's ip, so even a well formed one can't be used here
+    let player = json!({ "playabilityStatus": { "status": "OK" }, "videoDetails": { "videoId": "jNQXAC9IVRw" }, "timestamp": NOW });
+    assert!(!is_valid_peer_data("player", "jNQXAC9IVRw-en-US-false", &player, NOW, 3600));
+    assert!(!SHARED_COLLECTIONS.contains(&"player"));
+  }
+
+  #[test]
+  fn rejects_responses_for_other_videos() {
+    let next = json!({ "contents": {}, "currentVideoEndpoint": { "watchEndpoint": { "videoId": "dQw4w9WgXcQ" } }, "timestamp": NOW });
+    assert!(!is_valid_peer_data("next", "jNQXAC9IVRw-en-US", &next, NOW, 3600));
+    // a video id that only starts the same way
+    let next = json!({ "contents": {}, "currentVideoEndpoint": { "watchEndpoint": { "videoId": "jNQXAC9IVR" } }, "timestamp": NOW });
+    assert!(!is_valid_peer_data("next", "jNQXAC9IVRw-en-US", &next, NOW, 3600));
+    let next = json!({ "contents": {}, "timestamp": NOW });
+    assert!(!is_valid_peer_data("next", "jNQXAC9IVRw-en-US", &next, NOW, 3600));
+  }
+
+  #[test]
+  fn rejects_malformed_stale_and_expired_responses() {
+    let next = |extra: serde_json::Value| {
+      let mut next = json!({ "contents": {}, "currentVideoEndpoint": { "watchEndpoint": { "videoId": "jNQXAC9IVRw" } }, "timestamp": NOW });
+      next.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+      next
+    };
+    let key = "jNQXAC9IVRw-en-US";
+    assert!(!is_valid_peer_data("next", key, &json!({ "error": "not found" }), NOW, 3600));
+    assert!(!is_valid_peer_data("next", key, &next(json!({ "contents": "OK" })), NOW, 3600));
+    assert!(!is_valid_peer_data("next", key, &next(json!({ "timestamp": null })), NOW, 3600));
+    assert!(!is_valid_peer_data("next", key, &next(json!({ "timestamp": NOW + 3600 })), NOW, 3600));
+    assert!(!is_valid_peer_data("next", key, &next(json!({ "timestamp": NOW - 7200 })), NOW, 3600));
+    assert!(!is_valid_peer_data("next", key, &next(json!({ "expires": NOW - 1 })), NOW, 3600));
+    assert!(!is_valid_peer_data("next", key, &next(json!({ "stale": true })), NOW, 3600));
+    assert!(!is_valid_peer_data("channel", key, &next(json!({})), NOW, 3600));
+  }
+}
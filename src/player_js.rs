@@ -0,0 +1,109 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use yayti::parsers::ciphers::extract_sig_timestamp;
+use crate::helpers::{get_previous_data, DbWrapper};
+use crate::innertube::{fetch_player_js_id, fetch_player_js};
+use crate::routes::video::FetchPlayerError;
+use crate::settings::AppSettings;
+
+// the latest player.js, which the browser clients need for the signature timestamp and deciphering
+pub struct PlayerJs {
+  pub id: String,
+  pub response: String,
+  pub signature_timestamp: i32,
+  // when it was swapped in
+  pub updated_at: i64
+}
+
+// the current player.js without its source, for `/api/v1/stats`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlayerJsStats {
+  pub id: String,
+  #[serde(rename = "signatureTimestamp")]
+  pub signature_timestamp: i32,
+  #[serde(rename = "updatedAt")]
+  pub updated_at: i64
+}
+
+impl PlayerJs {
+  pub fn stats(&self) -> PlayerJsStats {
+    PlayerJsStats {
+      id: String::from(&self.id),
+      signature_timestamp: self.signature_timestamp,
+      updated_at: self.updated_at
+    }
+  }
+}
+
+// the cached player.js when it's still the one `/iframe_api` points at (it's kept until a new one rolls out)
+async fn get_cached_player_js(db: &DbWrapper, app_settings: &AppSettings, player_js_id: &str) -> Option<PlayerJs> {
+  let cached_player_js_id = get_previous_data("player", "player_js-id", db, app_settings).await?;
+  if cached_player_js_id.as_str() != Some(player_js_id) {
+    return None;
+  }
+  let response = get_previous_data("player", &format!("player_js-{}", player_js_id), db, app_settings).await?;
+  let signature_timestamp = get_previous_data("player", "signature_timestamp", db, app_settings).await?;
+  Some(PlayerJs {
+    id: String::from(player_js_id),
+    response: String::from(response.as_str()?),
+    signature_timestamp: signature_timestamp.as_i64()? as i32,
+    updated_at: Utc::now().timestamp()
+  })
+}
+
+async fn fetch_latest_player_js(db: &DbWrapper, app_settings: &AppSettings) -> Result<Arc<PlayerJs>, FetchPlayerError> {
+  let http = &app_settings.state.http;
+  let player_js_id = match fetch_player_js_id(http).await {
+    Ok(Some(player_js_id)) => player_js_id,
+    Ok(None) => return Err(FetchPlayerError::PlayerJsIdNotFound),
+    Err(error) => return Err(FetchPlayerError::Reqwest(Arc::new(error)))
+  };
+  if let Some(current_player_js) = app_settings.state.player_js().filter(|current_player_js| current_player_js.id == player_js_id) {
+    return Ok(current_player_js);
+  }
+  let player_js = match get_cached_player_js(db, app_settings, &player_js_id).await {
+    Some(player_js) => player_js,
+    None => {
+      let response = fetch_player_js(http, &player_js_id).await.map_err(|error| FetchPlayerError::Reqwest(Arc::new(error)))?;
+      let signature_timestamp = extract_sig_timestamp(&response).map_err(FetchPlayerError::SignatureTimestampNotFound)?;
+      db.delete("player", "player_js-id").await;
+      db.insert_json("player", "player_js-id", &json!(player_js_id)).await;
+      db.delete("player", &format!("player_js-{}", player_js_id)).await;
+      db.insert_json("player", &format!("player_js-{}", player_js_id), &json!(response)).await;
+      db.delete("player", "signature_timestamp").await;
+      db.insert_json("player", "signature_timestamp", &json!(signature_timestamp)).await;
+      PlayerJs {
+        id: player_js_id,
+        response,
+        signature_timestamp,
+        updated_at: Utc::now().timestamp()
+      }
+    }
+  };
+  let player_js = Arc::new(player_js);
+  match app_settings.state.swap_player_js(player_js.clone()) {
+    Some(previous_player_js) => log::info!("player.js changed from {} to {} (signature timestamp {})", previous_player_js.id, player_js.id, player_js.signature_timestamp),
+    None => log::info!("Using player.js {} (signature timestamp {})", player_js.id, player_js.signature_timestamp)
+  }
+  Ok(player_js)
+}
+
+// checks `/iframe_api` for a new player.js, and swaps it in (from the cache, or downloaded and parsed) when there is one
+pub async fn refresh_player_js(db: &DbWrapper, app_settings: &AppSettings) -> Result<Arc<PlayerJs>, FetchPlayerError> {
+  app_settings.coalesce("player", "player_js", fetch_latest_player_js(db, app_settings)).await
+}
+
+// checks for a new player.js at startup and every `--player-js-check-interval` seconds, so requests never have to wait for one to download
+pub fn spawn_player_js_watcher(app_settings: AppSettings) {
+  tokio::spawn(async move {
+    loop {
+      if let Err(error) = refresh_player_js(&app_settings.get_json_db().await, &app_settings).await {
+        log::warn!("Failed to check for a new player.js: {}", error);
+      }
+      tokio::time::sleep(Duration::from_secs(app_settings.player_js_check_interval)).await;
+    }
+  });
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use reqwest::{RequestBuilder, Response};
+use serde_json::{json, from_str, to_string_pretty, Value};
+
+// `--record-innertube` writes every upstream response to disk, and `--replay-innertube` serves them back instead of going upstream
+pub enum Recording {
+  Off,
+  Record(PathBuf),
+  Replay(PathBuf)
+}
+
+// FNV-1a, since the file names have to stay the same between runs and rust versions (unlike `DefaultHasher`)
+fn fnv1a(bytes: &[u8]) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in bytes {
+    hash ^= *byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+// the request (method, url and body) and the file name its response is stored under, ex: `POST-www.youtube.com-youtubei-v1-player-3f1d9c0a7e2b4c61`
+// requests with streaming bodies can't be inspected, so they're never recorded
+pub fn describe_request(request: &RequestBuilder) -> Option<(String, Value)> {
+  let request = request.try_clone()?.build().ok()?;
+  let body = request.body().and_then(|body| body.as_bytes()).unwrap_or(&[]);
+  let url = request.url().as_str();
+  let path = format!("{}{}", request.url().host_str().unwrap_or(""), request.url().path()).chars().map(|character| if character.is_ascii_alphanumeric() || character == '.' { character } else { '-' }).collect::<String>();
+  let hash = fnv1a(&[request.method().as_str().as_bytes(), url.as_bytes(), body].concat());
+  let name = format!("{}-{}-{:016x}", request.method(), path.trim_matches('-'), hash);
+  Some((name, json!({
+    "method": request.method().as_str(),
+    "url": url,
+    "body": String::from_utf8_lossy(body)
+  })))
+}
+
+fn to_response(status: u16, headers: &Value, body: Vec<u8>) -> Response {
+  let mut builder = ::http::Response::builder().status(status);
+  if let Some(headers) = headers.as_object() {
+    for (name, value) in headers {
+      if let Some(value) = value.as_str() {
+        builder = builder.header(name, value);
+      }
+    }
+  }
+  Response::from(builder.body(body).unwrap_or_default())
+}
+
+// reads the whole response, writes it next to the request that got it, and hands back an identical response
+pub async fn record(directory: &Path, name: &str, request: Value, response: Response) -> Result<Response, reqwest::Error> {
+  let status = response.status().as_u16();
+  let headers = response.headers().iter().filter(|(name, _)| {
+    // the recorded body is already decompressed, and its length might not match after that
+    let name = name.as_str();
+    name != "content-encoding" && name != "content-length" && name != "transfer-encoding"
+  }).filter_map(|(name, value)| value.to_str().ok().map(|value| (String::from(name.as_str()), json!(value)))).collect::<serde_json::Map<String, Value>>();
+  let headers = Value::Object(headers);
+  let body = response.bytes().await?.to_vec();
+  let metadata = json!({
+    "request": request,
+    "status": status,
+    "headers": headers
+  });
+  if let Err(error) = fs::create_dir_all(directory)
+    .and_then(|_| fs::write(directory.join(format!("{}.json", name)), to_string_pretty(&metadata).unwrap_or_default()))
+    .and_then(|_| fs::write(directory.join(format!("{}.body", name)), &body)) {
+    log::warn!("Failed to record {}: {}", name, error);
+  }
+  Ok(to_response(status, &headers, body))
+}
+
+// requests that weren't recorded get a `404` (replaying never goes upstream)
+pub fn replay(directory: &Path, name: &str) -> Response {
+  let metadata = fs::read_to_string(directory.join(format!("{}.json", name))).ok().and_then(|metadata| from_str::<Value>(&metadata).ok());
+  let body = fs::read(directory.join(format!("{}.body", name)));
+  match (metadata, body) {
+    (Some(metadata), Ok(body)) => to_response(metadata["status"].as_u64().unwrap_or(200) as u16, &metadata["headers"], body),
+    _ => {
+      log::warn!("No recording of {} to replay", name);
+      to_response(404, &json!({ "x-yaytapi-replay": "miss" }), vec![])
+    }
+  }
+}
@@ -2,20 +2,48 @@
 pub mod video;
 pub mod channel;
 pub mod playlist;
+pub mod auth;
+pub mod search;
+pub mod pages;
+pub mod manifest;
+pub mod shorts;
+pub mod piped;
+pub mod cache;
 use std::fs;
 use serde_json::from_str;
 use serde::{Serialize, Deserialize};
 use serde_json::{to_string_pretty, to_string};
 use actix_web::web::{Query, Data, Path};
-use actix_web::{HttpResponse, Responder, get, HttpRequest, routes};
+use actix_web::{HttpResponse, Responder, get, HttpRequest};
 use actix_web::http::StatusCode;
 use crate::settings::AppSettings;
+use crate::fallback::proxy_to_fallback;
+use std::collections::HashMap;
+use crate::state::{PeerStats, CircuitStats};
+use crate::sessions::SessionPoolStats;
+use crate::player_js::PlayerJsStats;
+use crate::innertube::InnertubeClient;
 
 #[derive(Serialize, Deserialize)]
 pub enum InnertubeEndpoint {
   Web,
   Android,
-  TV
+  TV,
+  Ios,
+  TvEmbedded,
+  WebEmbedded
+}
+
+impl From<&InnertubeClient> for InnertubeEndpoint {
+  fn from(client: &InnertubeClient) -> InnertubeEndpoint {
+    match client {
+      InnertubeClient::Web => InnertubeEndpoint::Web,
+      InnertubeClient::Android => InnertubeEndpoint::Android,
+      InnertubeClient::Ios => InnertubeEndpoint::Ios,
+      InnertubeClient::TvEmbedded => InnertubeEndpoint::TvEmbedded,
+      InnertubeClient::WebEmbedded => InnertubeEndpoint::WebEmbedded
+    }
+  }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -40,7 +68,15 @@ pub struct Stats {
   version: String,
   software: Software,
   // TODO ✏ add the rest of the schema from https://docs.invidious.io/api/#get-apiv1stats
-  yaytapi_settings: Option<YaytAPIStats>
+  yaytapi_settings: Option<YaytAPIStats>,
+  peers: Vec<PeerStats>,
+  // the health of each innertube endpoint, see `--circuit-breaker-threshold`
+  circuits: HashMap<String, CircuitStats>,
+  // the innertube sessions in use, see `--innertube-sessions`
+  sessions: SessionPoolStats,
+  // the player.js the browser clients are using, see `--player-js-check-interval`
+  #[serde(rename = "playerJs")]
+  player_js: Option<PlayerJsStats>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -75,9 +111,12 @@ pub async fn server_stats(params: Query<StatsQueryParams>, app_settings: Data<Ap
         local_streaming_enabled: app_settings.enable_local_streaming,
         decipher_streams_enabled: app_settings.decipher_streams,
         innertube_endpoints_used: {
+          // web is always used for everything besides `/player`, then whichever player clients have worked
           let mut endpoints = vec!(InnertubeEndpoint::Web);
-          if app_settings.use_android_endpoint_for_streams {
-            endpoints.push(InnertubeEndpoint::Android);
+          for client in app_settings.state.player_clients_used.lock().unwrap().iter() {
+            if client != &InnertubeClient::Web {
+              endpoints.push(InnertubeEndpoint::from(client));
+            }
           }
           endpoints
         },
@@ -85,7 +124,11 @@ pub async fn server_stats(params: Query<StatsQueryParams>, app_settings: Data<Ap
       })
     } else {
       None
-    }
+    },
+    peers: app_settings.state.peers.lock().unwrap().clone(),
+    circuits: app_settings.state.circuits.lock().unwrap().clone(),
+    sessions: app_settings.state.http.sessions.stats(),
+    player_js: app_settings.state.player_js().map(|player_js| player_js.stats())
   };
   let json_response = match if is_pretty {
     to_string_pretty(&stats)
@@ -119,15 +162,15 @@ pub async fn static_files(params: Path<String>) -> impl Responder {
 }
 
 #[get("/{path:.*}")]
-pub async fn not_found(params: Path<String>) -> impl Responder {
+pub async fn not_found(req: HttpRequest, params: Path<String>, app_settings: Data<AppSettings>) -> impl Responder {
+  if let Some(response) = proxy_to_fallback(&req, &app_settings).await {
+    return response;
+  }
   let path = params.into_inner();
   HttpResponse::build(StatusCode::from_u16(404).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"The requested path '/{}' was not found on this server.\" }}", path))
 }
 
-#[routes]
 #[get("/")]
-#[get("/watch")]
-#[get("/playlist")]
 pub async fn homepage() -> impl Responder {
-  HttpResponse::build(StatusCode::from_u16(200).unwrap()).content_type("text/html").body(actix_web::web::Bytes::from(include_bytes!("../static/home.html").into_iter().map(|u| u.to_owned()).collect::<Vec::<u8>>()))
+  HttpResponse::build(StatusCode::from_u16(200).unwrap()).content_type("text/html").body(actix_web::web::Bytes::from(include_bytes!("../static/home.html").iter().map(|u| u.to_owned()).collect::<Vec::<u8>>()))
 }
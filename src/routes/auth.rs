@@ -0,0 +1,341 @@
+use std::sync::OnceLock;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use serde_json::{json, Value, to_string_pretty, to_string, from_value};
+use chrono::Utc;
+use actix_web::web::{Path, Data, Query, Bytes};
+use actix_web::{HttpResponse, Responder, HttpRequest, get, post, delete};
+use actix_web::http::StatusCode;
+use crate::settings::AppSettings;
+use crate::helpers::DbWrapper;
+use crate::state::{KeyedLocks, KeyedLockGuard};
+use crate::import::{ImportFormat, parse_import, apply_import};
+use crate::export::{ExportFormat, export_user_data};
+
+// yaytapi doesn't have accounts, so the token a client sends is the user
+// it can be sent as `Authorization: Bearer <token>` or as the `SID` cookie (same as iv)
+pub fn get_user_token(req: &HttpRequest, app_settings: &AppSettings) -> Option<String> {
+  if !app_settings.enable_auth {
+    return None;
+  }
+  let bearer = match req.headers().get("Authorization").map(|header| header.to_str()) {
+    Some(Ok(header)) => header.strip_prefix("Bearer ").map(|token| String::from(token.trim())),
+    _ => None
+  };
+  let token = match bearer {
+    Some(token) => Some(token),
+    None => req.cookie("SID").map(|cookie| String::from(cookie.value()))
+  };
+  token.filter(|token| !token.is_empty())
+}
+
+fn unauthorized() -> HttpResponse {
+  HttpResponse::build(StatusCode::from_u16(401).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"This endpoint requires a token sent as `Authorization: Bearer <token>` or as the `SID` cookie.\" }")
+}
+
+fn json_response<T: Serialize>(value: &T, is_pretty: bool) -> HttpResponse {
+  match if is_pretty {
+    to_string_pretty(value)
+  } else {
+    to_string(value)
+  } {
+    Ok(json_response) => HttpResponse::Ok().content_type("application/json").body(json_response),
+    Err(_) => {
+      HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }")
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+  #[serde(rename = "videoId")]
+  pub video_id: String,
+  // unix timestamp of the last time the video was watched
+  pub watched: i64,
+  // last playback position in seconds
+  pub position: u64
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Subscription {
+  #[serde(rename = "authorId")]
+  pub author_id: String,
+  pub author: String
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserPlaylist {
+  #[serde(rename = "playlistId")]
+  pub playlist_id: String,
+  pub title: String,
+  pub description: String,
+  // video ids in playlist order
+  pub videos: Vec<String>,
+  pub updated: i64
+}
+
+// every kind of user data is stored as `{ "<field>": [...] }` under the user's token
+async fn get_user_data<T: DeserializeOwned>(db: &DbWrapper, collection: &str, field: &str, token: &str) -> Vec<T> {
+  match db.seek_for_json(collection, token).await {
+    Some(data) => from_value::<Vec<T>>(data[field].clone()).unwrap_or(vec![]),
+    None => vec![]
+  }
+}
+
+async fn set_user_data<T: Serialize>(db: &DbWrapper, collection: &str, field: &str, token: &str, data: &[T]) {
+  db.delete(collection, token).await;
+  db.insert_json(collection, token, &json!({ field: data })).await;
+}
+
+// held by token while a user's data is read, changed and written back, so concurrent changes don't overwrite each other
+static USER_DATA_LOCKS: OnceLock<KeyedLocks> = OnceLock::new();
+
+pub async fn lock_user_data(token: &str) -> KeyedLockGuard<'static> {
+  USER_DATA_LOCKS.get_or_init(KeyedLocks::default).lock(token).await
+}
+
+// newest entries are kept at the front
+pub async fn get_history(db: &DbWrapper, token: &str) -> Vec<HistoryEntry> {
+  get_user_data(db, "history", "videos", token).await
+}
+
+// only the newest `--max-history` entries are kept (along with their resume positions)
+pub async fn set_history(db: &DbWrapper, token: &str, history: &[HistoryEntry], max_history: usize) {
+  let kept = history.len().min(max_history);
+  for entry in &history[kept..] {
+    delete_resume_position(db, token, &entry.video_id).await;
+  }
+  set_user_data(db, "history", "videos", token, &history[..kept]).await;
+}
+
+// positions are also kept by video, so `resume_at` doesn't have to read the whole history
+fn resume_position_key(token: &str, video_id: &str) -> String {
+  format!("{}-{}", token, video_id)
+}
+
+pub async fn set_resume_position(db: &DbWrapper, token: &str, video_id: &str, position: u64) {
+  let key = resume_position_key(token, video_id);
+  db.delete("resume-positions", &key).await;
+  db.insert_json("resume-positions", &key, &json!({ "position": position })).await;
+}
+
+async fn delete_resume_position(db: &DbWrapper, token: &str, video_id: &str) {
+  db.delete("resume-positions", &resume_position_key(token, video_id)).await;
+}
+
+pub async fn get_subscriptions(db: &DbWrapper, token: &str) -> Vec<Subscription> {
+  get_user_data(db, "subscriptions", "subscriptions", token).await
+}
+
+pub async fn set_subscriptions(db: &DbWrapper, token: &str, subscriptions: &[Subscription]) {
+  set_user_data(db, "subscriptions", "subscriptions", token, subscriptions).await;
+}
+
+pub async fn get_playlists(db: &DbWrapper, token: &str) -> Vec<UserPlaylist> {
+  get_user_data(db, "user-playlists", "playlists", token).await
+}
+
+pub async fn set_playlists(db: &DbWrapper, token: &str, playlists: &[UserPlaylist]) {
+  set_user_data(db, "user-playlists", "playlists", token, playlists).await;
+}
+
+// preferences are kept as whatever object the client (or an import) gave us
+pub async fn get_preferences(db: &DbWrapper, token: &str) -> Option<Value> {
+  db.seek_for_json("preferences", token).await
+}
+
+pub async fn set_preferences(db: &DbWrapper, token: &str, preferences: &Value) {
+  db.delete("preferences", token).await;
+  db.insert_json("preferences", token, preferences).await;
+}
+
+// where to pick a video back up for the given user
+pub async fn get_resume_position(db: &DbWrapper, token: &str, video_id: &str) -> Option<u64> {
+  db.seek_for_json("resume-positions", &resume_position_key(token, video_id)).await?["position"].as_u64()
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQueryParams {
+  page: Option<usize>,
+  max_results: Option<usize>,
+  pretty: Option<i32>
+}
+
+#[get("/api/v1/auth/history")]
+pub async fn list_history(req: HttpRequest, query: Query<HistoryQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let page = query.page.unwrap_or(1).max(1);
+  let max_results = query.max_results.unwrap_or(100);
+  let db = app_settings.get_json_db().await;
+  let history = get_history(&db, &token).await.into_iter().skip((page - 1) * max_results).take(max_results).collect::<Vec::<HistoryEntry>>();
+  json_response(&history, query.pretty.unwrap_or(0) == 1)
+}
+
+#[derive(Deserialize)]
+pub struct AddHistoryQueryParams {
+  position: Option<u64>
+}
+
+#[post("/api/v1/auth/history/{video_id}")]
+pub async fn add_history(req: HttpRequest, path: Path<String>, query: Query<AddHistoryQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let video_id = path.into_inner();
+  let db = app_settings.get_json_db().await;
+  let _lock = lock_user_data(&token).await;
+  let mut history = get_history(&db, &token).await;
+  // keep the previous position if this is just another watch without one
+  let previous_position = history.iter().find(|entry| entry.video_id == video_id).map(|entry| entry.position);
+  let position = query.position.or(previous_position).unwrap_or(0);
+  history.retain(|entry| entry.video_id != video_id);
+  set_resume_position(&db, &token, &video_id, position).await;
+  history.insert(0, HistoryEntry {
+    video_id,
+    watched: Utc::now().timestamp(),
+    position
+  });
+  set_history(&db, &token, &history, app_settings.max_history).await;
+  HttpResponse::build(StatusCode::from_u16(204).unwrap()).finish()
+}
+
+#[delete("/api/v1/auth/history/{video_id}")]
+pub async fn delete_history(req: HttpRequest, path: Path<String>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let video_id = path.into_inner();
+  let db = app_settings.get_json_db().await;
+  let _lock = lock_user_data(&token).await;
+  let mut history = get_history(&db, &token).await;
+  history.retain(|entry| entry.video_id != video_id);
+  delete_resume_position(&db, &token, &video_id).await;
+  set_history(&db, &token, &history, app_settings.max_history).await;
+  HttpResponse::build(StatusCode::from_u16(204).unwrap()).finish()
+}
+
+#[delete("/api/v1/auth/history")]
+pub async fn clear_history(req: HttpRequest, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let db = app_settings.get_json_db().await;
+  let _lock = lock_user_data(&token).await;
+  for entry in get_history(&db, &token).await {
+    delete_resume_position(&db, &token, &entry.video_id).await;
+  }
+  db.delete("history", &token).await;
+  HttpResponse::build(StatusCode::from_u16(204).unwrap()).finish()
+}
+
+#[derive(Deserialize)]
+pub struct AuthQueryParams {
+  pretty: Option<i32>
+}
+
+#[get("/api/v1/auth/subscriptions")]
+pub async fn list_subscriptions(req: HttpRequest, query: Query<AuthQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let db = app_settings.get_json_db().await;
+  json_response(&get_subscriptions(&db, &token).await, query.pretty.unwrap_or(0) == 1)
+}
+
+#[get("/api/v1/auth/playlists")]
+pub async fn list_playlists(req: HttpRequest, query: Query<AuthQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let db = app_settings.get_json_db().await;
+  json_response(&get_playlists(&db, &token).await, query.pretty.unwrap_or(0) == 1)
+}
+
+#[derive(Deserialize)]
+pub struct ImportQueryParams {
+  // opml, newpipe, freetube, invidious or takeout (detected from the contents when missing)
+  format: Option<String>,
+  // the original file name (takeout playlist csvs are named after the playlist)
+  name: Option<String>,
+  pretty: Option<i32>
+}
+
+#[post("/api/v1/auth/import")]
+pub async fn import_data(req: HttpRequest, body: Bytes, query: Query<ImportQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let contents = String::from_utf8_lossy(&body);
+  let file_name = query.name.clone().unwrap_or(String::from(""));
+  let format = match &query.format {
+    Some(format) => match ImportFormat::from_name(format) {
+      Some(format) => format,
+      None => {
+        return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Unknown import format: {}\" }}", format.replace('"', "\\\"")));
+      }
+    },
+    None => match ImportFormat::detect(&file_name, &contents) {
+      Some(format) => format,
+      None => {
+        return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Unable to detect the import format, please specify `format`\" }");
+      }
+    }
+  };
+  let data = match parse_import(&format, &file_name, &contents) {
+    Ok(data) => data,
+    Err(error) => {
+      return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Failed to parse import\", \"inner_message\": \"{}\" }}", error.replace('"', "\\\"")));
+    }
+  };
+  let summary = apply_import(&app_settings, &token, data).await;
+  json_response(&summary, query.pretty.unwrap_or(0) == 1)
+}
+
+#[derive(Deserialize)]
+pub struct ExportQueryParams {
+  format: Option<String>,
+  // which of freetube's files to export
+  file: Option<String>
+}
+
+#[get("/api/v1/auth/export")]
+pub async fn export_data(req: HttpRequest, query: Query<ExportQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(token) = get_user_token(&req, &app_settings) else { return unauthorized() };
+  let format_name = query.format.clone().unwrap_or(String::from("invidious"));
+  let Some(format) = ExportFormat::from_name(&format_name, query.file.as_deref()) else {
+    if format_name.eq_ignore_ascii_case("freetube") {
+      return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"FreeTube exports need a file\", \"available_files\": [\"subscriptions\",\"playlists\",\"history\"] }");
+    }
+    return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Unknown export format: {}\", \"available_formats\": [\"invidious\",\"newpipe\",\"freetube\",\"opml\"] }}", format_name.replace('"', "\\\"")));
+  };
+  let db = app_settings.get_json_db().await;
+  match export_user_data(&format, &db, &token).await {
+    Ok(export) => HttpResponse::Ok()
+      .content_type(format.content_type())
+      .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", format.file_name())))
+      .body(export),
+    Err(_) => {
+      HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }")
+    }
+  }
+}
+
+// these need somewhere to keep the user data
+#[cfg(all(test, feature = "unqlite"))]
+mod tests {
+  use crate::settings::AppSettings;
+  use super::{HistoryEntry, get_history, set_history, set_resume_position, get_resume_position};
+
+  fn db_settings(name: &str) -> AppSettings {
+    let db_name = std::env::temp_dir().join(format!("yaytapi-{}.db", name));
+    let _ = std::fs::remove_file(&db_name);
+    AppSettings::from_cli_args(&[String::from("yaytapi"), format!("--db-name={}", db_name.display())])
+  }
+
+  fn entry(video_id: &str, watched: i64, position: u64) -> HistoryEntry {
+    HistoryEntry { video_id: String::from(video_id), watched, position }
+  }
+
+  #[actix_web::test]
+  async fn caps_the_history_and_forgets_the_dropped_positions() {
+    let app_settings = db_settings("history-cap");
+    let db = app_settings.get_json_db().await;
+    let history = vec![entry("dQw4w9WgXcQ", 2, 30), entry("jNQXAC9IVRw", 1, 12)];
+    for entry in &history {
+      set_resume_position(&db, "token", &entry.video_id, entry.position).await;
+    }
+    set_history(&db, "token", &history, 1).await;
+    assert_eq!(get_history(&db, "token").await.iter().map(|entry| entry.video_id.as_str()).collect::<Vec::<&str>>(), vec!["dQw4w9WgXcQ"]);
+    assert_eq!(get_resume_position(&db, "token", "dQw4w9WgXcQ").await, Some(30));
+    assert_eq!(get_resume_position(&db, "token", "jNQXAC9IVRw").await, None);
+    // positions are per user
+    assert_eq!(get_resume_position(&db, "other-token", "dQw4w9WgXcQ").await, None);
+  }
+}
@@ -0,0 +1,21 @@
+use actix_web::web::{Path, Data};
+use actix_web::{HttpResponse, HttpRequest, Responder, get};
+use actix_web::http::StatusCode;
+use crate::helpers::get_previous_data;
+use crate::peers::{SHARED_COLLECTIONS, is_peer_request};
+use crate::settings::AppSettings;
+
+// lets peers read this instance's cached innertube responses (only when this instance has peers of its own, and they send `--peer-secret`)
+// it never asks its own peers, so a miss can't bounce around the pool
+#[get("/api/v1/cache/{collection}/{key:.*}")]
+pub async fn cache_endpoint(req: HttpRequest, params: Path<(String, String)>, app_settings: Data<AppSettings>) -> impl Responder {
+  let (collection, key) = params.into_inner();
+  if app_settings.peers.is_empty() || !is_peer_request(&req, &app_settings) || !SHARED_COLLECTIONS.contains(&collection.as_str()) {
+    return HttpResponse::build(StatusCode::from_u16(403).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"This cache isn't shared\" }");
+  }
+  let db = app_settings.get_json_db().await;
+  match get_previous_data(&collection, &key, &db, &app_settings).await {
+    Some(previous_data) => HttpResponse::Ok().content_type("application/json").body(previous_data.to_string()),
+    None => HttpResponse::build(StatusCode::from_u16(404).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Not cached\" }")
+  }
+}
@@ -1,22 +1,74 @@
-
-
-use actix_web::web::{Path};
+use actix_web::web::{Path, Data};
 use actix_web::{HttpResponse, Responder, get};
-use reqwest::Client;
+use chrono::Utc;
+use serde_json::{from_str, Value};
 use substring::Substring;
+use std::fmt::{Formatter, Display};
+use std::sync::Arc;
+use crate::helpers::{get_previous_data, DbWrapper};
+use crate::innertube::{ContentLocale, fetch_browse};
+use crate::circuit::{fetch_upstream, Upstream};
+use crate::settings::AppSettings;
+use crate::http::HttpError;
 
 #[get("/ggpht/{author_thumbnail_url:.*}")]
-pub async fn author_thumbnail_proxy(params: Path<String>) -> impl Responder {
+pub async fn author_thumbnail_proxy(params: Path<String>, app_settings: Data<AppSettings>) -> impl Responder {
   let mut author_thumbnail_url = String::from(&params.into_inner());
   // Remove any starting slashes because ft sometimes adds an extra slash, and yt3.ggpht.com doesn't like that.
   while author_thumbnail_url.starts_with("/") {
     author_thumbnail_url = String::from(author_thumbnail_url.substring(1, author_thumbnail_url.len())); 
   }
-  let client = Client::new();
-  match client.get(format!("https://yt3.ggpht.com/{}", author_thumbnail_url)).send().await {
+  let http = &app_settings.state.http;
+  match http.send(http.client.get(format!("https://yt3.ggpht.com/{}", author_thumbnail_url))).await {
     Ok(thumbnail_response) => {
       HttpResponse::Ok().content_type(thumbnail_response.headers()["Content-Type"].to_str().unwrap()).streaming(thumbnail_response.bytes_stream())
     },
     Err(_err) => HttpResponse::Ok().body("error")
   }
 }
+
+#[derive(Clone)]
+pub enum FetchChannelError {
+  Reqwest(Arc<HttpError>),
+  FailedToParseChannel(Arc<serde_json::Error>),
+  ChannelNotFound
+}
+
+impl Display for FetchChannelError {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+      FetchChannelError::Reqwest(error) => format!("Error making request to innertube {}", error),
+      FetchChannelError::FailedToParseChannel(error) => format!("Failed to parse the `browse` response: {}", error),
+      FetchChannelError::ChannelNotFound => String::from("Channel not found")
+    })
+  }
+}
+
+// the videos tab of the channel
+const CHANNEL_VIDEOS_PARAMS: &str = "EgZ2aWRlb3PyBgQKAjoA";
+
+async fn fetch_channel_from_cache_or_upstream(db: &DbWrapper, app_settings: &AppSettings, channel_id: &str, locale: &ContentLocale, key: &str) -> Result<Value, FetchChannelError> {
+  match get_previous_data("channel", key, db, app_settings).await {
+    Some(previous_data) => Ok(previous_data),
+    None => {
+      let channel = match fetch_upstream("browse", "channel", key, db, app_settings, fetch_browse(&app_settings.state.http, channel_id, Some(CHANNEL_VIDEOS_PARAMS), locale)).await.map_err(|error| FetchChannelError::Reqwest(Arc::new(error)))? {
+        Upstream::Fresh(channel) => channel,
+        Upstream::Stale(channel_value) => return Ok(channel_value)
+      };
+      let mut channel_value = from_str::<Value>(&channel).map_err(|error| FetchChannelError::FailedToParseChannel(Arc::new(error)))?;
+      if !channel_value["metadata"]["channelMetadataRenderer"].is_object() {
+        return Err(FetchChannelError::ChannelNotFound);
+      }
+      channel_value["timestamp"] = Utc::now().timestamp().into();
+      if app_settings.cache_requests {
+        db.insert_json("channel", key, &channel_value).await;
+      }
+      Ok(channel_value)
+    }
+  }
+}
+
+pub async fn fetch_channel_with_cache(db: &DbWrapper, app_settings: &AppSettings, channel_id: &str, locale: &ContentLocale) -> Result<Value, FetchChannelError> {
+  let key = format!("{}-{}", channel_id, locale.cache_key());
+  app_settings.coalesce("channel", &key, fetch_channel_from_cache_or_upstream(db, app_settings, channel_id, locale, &key)).await
+}
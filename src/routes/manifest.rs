@@ -0,0 +1,112 @@
+use actix_web::web::{Path, Data, Query};
+use actix_web::{HttpResponse, HttpRequest, Responder, get};
+use serde::Deserialize;
+use serde_json::{Value, Map};
+use urlencoding::encode;
+use yayti::parsers::web::video::fmt_inv;
+use crate::innertube::ContentLocale;
+use crate::routes::video::{fetch_player_with_cache, player_error_response};
+use crate::settings::AppSettings;
+
+fn escape_xml(value: &str) -> String {
+  value.replace("&", "&amp;").replace("\"", "&quot;").replace("'", "&apos;").replace("<", "&lt;").replace(">", "&gt;")
+}
+
+// ex: `video/mp4; codecs="avc1.4d401f"` -> (`video/mp4`, `avc1.4d401f`)
+fn split_mime_type(mime_type: &str) -> (String, String) {
+  let mut parts = mime_type.splitn(2, ';');
+  let container = String::from(parts.next().unwrap_or("").trim());
+  let codecs = String::from(parts.next().unwrap_or("").trim().trim_start_matches("codecs=").trim_matches('"'));
+  (container, codecs)
+}
+
+// only the formats with their init and index ranges can be played through a manifest, and every url goes through `/latest_version`
+fn get_representation(format: &Value, video_id: &str, local: bool, locale: &ContentLocale) -> Option<String> {
+  let (Some(init), Some(index)) = (format["init"].as_str(), format["index"].as_str()) else { return None };
+  let itag = format["itag"].as_i64().or(format["itag"].as_str().and_then(|itag| itag.parse::<i64>().ok()))?;
+  let (_, codecs) = split_mime_type(format["type"].as_str().unwrap_or(""));
+  let bandwidth = format["bitrate"].as_i64().or(format["bitrate"].as_str().and_then(|bitrate| bitrate.parse::<i64>().ok())).unwrap_or(0);
+  let url = format!("/latest_version?id={}&itag={}&local={}&hl={}&gl={}", video_id, itag, local, encode(&locale.hl), encode(&locale.gl));
+  let attributes = match format["resolution"].as_str().or(format["size"].as_str()).and_then(|size| size.split_once('x')) {
+    Some((width, height)) => format!(" width=\"{}\" height=\"{}\" frameRate=\"{}\"", width, height, format["fps"].as_i64().unwrap_or(30)),
+    None => String::from("")
+  };
+  Some(format!("      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\"{}>\n        <BaseURL>{}</BaseURL>\n        <SegmentBase indexRange=\"{}\">\n          <Initialization range=\"{}\" />\n        </SegmentBase>\n      </Representation>", itag, escape_xml(&codecs), bandwidth, attributes, escape_xml(&url), escape_xml(index), escape_xml(init)))
+}
+
+// one adaptation set per container (ex: `audio/mp4`, `audio/webm`, `video/mp4`, and `video/webm`), in the order they first appear
+pub fn build_dash_manifest(video: &Map<String, Value>, video_id: &str, local: bool, locale: &ContentLocale) -> String {
+  let empty_vec = vec![];
+  let mut adaptation_sets = Vec::<(String, Vec<String>)>::new();
+  for format in video.get("adaptiveFormats").and_then(|formats| formats.as_array()).unwrap_or(&empty_vec) {
+    let Some(representation) = get_representation(format, video_id, local, locale) else { continue };
+    let (container, _) = split_mime_type(format["type"].as_str().unwrap_or(""));
+    match adaptation_sets.iter_mut().find(|(existing, _)| *existing == container) {
+      Some((_, representations)) => representations.push(representation),
+      None => adaptation_sets.push((container, vec![representation]))
+    }
+  }
+  let length_seconds = video.get("lengthSeconds").and_then(|length| length.as_i64().or(length.as_str().and_then(|length| length.parse::<i64>().ok()))).unwrap_or(0);
+  let adaptation_sets = adaptation_sets.into_iter().enumerate().map(|(i, (container, representations))| {
+    format!("    <AdaptationSet id=\"{}\" mimeType=\"{}\" startWithSAP=\"1\" subsegmentAlignment=\"true\">\n{}\n    </AdaptationSet>", i, escape_xml(&container), representations.join("\n"))
+  }).collect::<Vec::<String>>();
+  format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:full:2011\" minBufferTime=\"PT1.5S\" type=\"static\" mediaPresentationDuration=\"PT{}S\">\n  <Period>\n{}\n  </Period>\n</MPD>\n", length_seconds, adaptation_sets.join("\n"))
+}
+
+#[derive(Deserialize)]
+pub struct DashManifestQueryParams {
+  local: Option<bool>,
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>
+}
+
+// the adaptive formats (the only ones above 360p) for players that understand DASH
+#[get("/api/manifest/dash/id/{video_id}")]
+pub async fn dash_manifest(req: HttpRequest, path: Path<String>, query: Query<DashManifestQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let video_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let connection_info = req.connection_info().clone();
+  let uri = format!("{}://{}", connection_info.scheme(), connection_info.host());
+  let local = query.local.unwrap_or(app_settings.enable_local_streaming) && app_settings.enable_local_streaming;
+  let player_res = match fetch_player_with_cache(&video_id, &locale, &app_settings, local, Some(&uri)).await {
+    Ok(player_res) => player_res,
+    Err(error) => return player_error_response(&error)
+  };
+  let video = fmt_inv(&player_res, &locale.hl);
+  HttpResponse::Ok().content_type("application/dash+xml").body(build_dash_manifest(&video, &video_id, local, &locale))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::{json, Value, Map};
+  use crate::innertube::ContentLocale;
+  use super::build_dash_manifest;
+
+  fn video(adaptive_formats: Value) -> Map<String, Value> {
+    json!({ "lengthSeconds": 19, "adaptiveFormats": adaptive_formats }).as_object().unwrap().clone()
+  }
+
+  #[test]
+  fn groups_the_adaptive_formats_by_container() {
+    let manifest = build_dash_manifest(&video(json!([
+      { "itag": "137", "type": "video/mp4; codecs=\"avc1.640028\"", "bitrate": "4000000", "init": "0-740", "index": "741-1000", "resolution": "1920x1080", "fps": 30 },
+      { "itag": "140", "type": "audio/mp4; codecs=\"mp4a.40.2\"", "bitrate": "130000", "init": "0-631", "index": "632-700" },
+      { "itag": "136", "type": "video/mp4; codecs=\"avc1.4d401f\"", "bitrate": "2000000", "init": "0-739", "index": "740-999", "resolution": "1280x720", "fps": 30 }
+    ])), "jNQXAC9IVRw", false, &ContentLocale::default());
+    assert!(manifest.contains("mediaPresentationDuration=\"PT19S\""));
+    assert_eq!(manifest.matches("<AdaptationSet").count(), 2);
+    assert!(manifest.find("id=\"136\"").unwrap() < manifest.find("mimeType=\"audio/mp4\"").unwrap());
+    assert!(manifest.contains("<Representation id=\"137\" codecs=\"avc1.640028\" bandwidth=\"4000000\" width=\"1920\" height=\"1080\" frameRate=\"30\">"));
+    assert!(manifest.contains("<BaseURL>/latest_version?id=jNQXAC9IVRw&amp;itag=140&amp;local=false&amp;hl=en&amp;gl=US</BaseURL>"));
+    assert!(manifest.contains("<SegmentBase indexRange=\"632-700\">"));
+  }
+
+  #[test]
+  fn skips_formats_without_ranges() {
+    let manifest = build_dash_manifest(&video(json!([
+      { "itag": "140", "type": "audio/mp4; codecs=\"mp4a.40.2\"", "bitrate": "130000" }
+    ])), "jNQXAC9IVRw", false, &ContentLocale::default());
+    assert!(!manifest.contains("<AdaptationSet"));
+  }
+}
@@ -0,0 +1,498 @@
+use askama::Template;
+use actix_web::web::{Path, Data, Query};
+use actix_web::{HttpResponse, HttpRequest, Responder, get};
+use actix_web::http::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value, Map};
+use urlencoding::encode;
+use yayti::parsers::web::{video::fmt_inv, playlist::parse};
+use crate::innertube::ContentLocale;
+use crate::local::video_link_or_id_to_id;
+use crate::parsers::channel::fmt_inv_channel;
+use crate::parsers::search::{get_search_results, get_search_continuation};
+use crate::routes::video::{fetch_player_with_cache, player_error_status};
+use crate::routes::playlist::fetch_playlist_with_cache;
+use crate::routes::channel::fetch_channel_with_cache;
+use crate::routes::search::fetch_search_with_cache;
+use crate::settings::AppSettings;
+
+// ex: 3723 -> `1:02:03`
+fn fmt_length(length_seconds: i64) -> String {
+  let (hours, minutes, seconds) = (length_seconds / 3600, (length_seconds % 3600) / 60, length_seconds % 60);
+  if hours > 0 {
+    format!("{}:{:02}:{:02}", hours, minutes, seconds)
+  } else {
+    format!("{}:{:02}", minutes, seconds)
+  }
+}
+
+fn get_str(map: &Map<String, Value>, key: &str) -> String {
+  String::from(map.get(key).and_then(|value| value.as_str()).unwrap_or(""))
+}
+
+fn get_i64(map: &Map<String, Value>, key: &str) -> i64 {
+  match map.get(key) {
+    Some(value) => value.as_i64().or(value.as_str().and_then(|value| value.parse::<i64>().ok())).unwrap_or(0),
+    None => 0
+  }
+}
+
+// the public facing url, used for absolute links in link previews
+pub fn get_base_url(req: &HttpRequest, app_settings: &AppSettings) -> String {
+  let connection_info = req.connection_info();
+  app_settings.pub_url.clone().unwrap_or(format!("{}://{}", connection_info.scheme(), connection_info.host()))
+}
+
+fn render<T: Template>(template: &T, status_code: u16) -> HttpResponse {
+  match template.render() {
+    Ok(html) => HttpResponse::build(StatusCode::from_u16(status_code).unwrap()).content_type("text/html; charset=utf-8").body(html),
+    Err(error) => HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("text/plain").body(format!("Failed to render page: {}", error))
+  }
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+  message: String
+}
+
+fn error_page(status_code: u16, message: String) -> HttpResponse {
+  render(&ErrorTemplate { message }, status_code)
+}
+
+struct VideoSource {
+  url: String,
+  mime_type: String,
+  label: String
+}
+
+#[derive(Template)]
+#[template(path = "watch.html")]
+struct WatchTemplate {
+  base_url: String,
+  video_id: String,
+  title: String,
+  author: String,
+  author_id: String,
+  description: String,
+  thumbnail: String,
+  view_count: i64,
+  length: String,
+  start: i64,
+  oembed_url: String,
+  sources: Vec<VideoSource>,
+  // for browsers (and external players) that can play the adaptive formats
+  dash_url: String,
+  playlist: Option<WatchPlaylist>
+}
+
+// the playlist a video is being watched from (`/watch?v=...&list=...`)
+struct WatchPlaylist {
+  playlist_id: String,
+  title: String,
+  videos: Vec<ListVideo>,
+  // where the current video is in `videos`
+  index: usize,
+  next_video_id: Option<String>
+}
+
+// the muxed streams are the only ones every `<video>` can play (usually just 360p, the rest is in the DASH manifest), and `/latest_version` always redirects to a fresh url
+// when `listen` is set, only the audio streams are returned
+fn get_video_sources(video: &Map<String, Value>, video_id: &str, local: bool, locale: &ContentLocale, listen: bool) -> Vec<VideoSource> {
+  let empty_vec = vec![];
+  let formats = if listen {
+    video.get("adaptiveFormats").and_then(|formats| formats.as_array()).unwrap_or(&empty_vec).iter().filter(|format| format["type"].as_str().unwrap_or("").starts_with("audio/")).collect::<Vec::<&Value>>()
+  } else {
+    video.get("formatStreams").and_then(|formats| formats.as_array()).unwrap_or(&empty_vec).iter().collect::<Vec::<&Value>>()
+  };
+  formats.into_iter().filter_map(|format| {
+    let itag = format["itag"].as_i64().or(format["itag"].as_str().and_then(|itag| itag.parse::<i64>().ok()))?;
+    Some(VideoSource {
+      url: format!("/latest_version?id={}&itag={}&local={}&hl={}&gl={}", video_id, itag, local, encode(&locale.hl), encode(&locale.gl)),
+      mime_type: String::from(format["type"].as_str().unwrap_or("video/mp4").split(';').next().unwrap_or("video/mp4")),
+      label: String::from(format["qualityLabel"].as_str().unwrap_or(""))
+    })
+  }).collect()
+}
+
+fn get_oembed_url(base_url: &str, video_id: &str) -> String {
+  format!("{}/oembed?format=json&url={}", base_url, encode(&format!("{}/watch?v={}", base_url, video_id)))
+}
+
+#[derive(Deserialize)]
+pub struct WatchPageQueryParams {
+  v: Option<String>,
+  list: Option<String>,
+  t: Option<String>,
+  local: Option<bool>,
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>
+}
+
+#[get("/watch")]
+pub async fn watch_page(req: HttpRequest, query: Query<WatchPageQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(video_id) = query.v.clone() else { return error_page(400, String::from("Missing video id `v`")) };
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let base_url = get_base_url(&req, &app_settings);
+  let local = query.local.unwrap_or(app_settings.enable_local_streaming) && app_settings.enable_local_streaming;
+  let player_res = match fetch_player_with_cache(&video_id, &locale, &app_settings, local, Some(&base_url)).await {
+    Ok(player_res) => player_res,
+    Err(error) => return error_page(player_error_status(&error), format!("{}", error))
+  };
+  let video = fmt_inv(&player_res, &locale.hl);
+  let sources = get_video_sources(&video, &video_id, local, &locale, false);
+  let dash_url = format!("/api/manifest/dash/id/{}?local={}&hl={}&gl={}", video_id, local, encode(&locale.hl), encode(&locale.gl));
+  // the video still plays when its playlist can't be loaded
+  let playlist = match &query.list {
+    Some(playlist_id) => match fetch_page_playlist(&app_settings, playlist_id, &locale).await {
+      Ok(playlist) => {
+        let videos = get_list_videos(&playlist);
+        let index = videos.iter().position(|video| video.video_id == video_id).unwrap_or(0);
+        Some(WatchPlaylist {
+          playlist_id: String::from(playlist_id),
+          title: get_str(&playlist, "title"),
+          next_video_id: videos.get(index + 1).map(|video| String::from(&video.video_id)),
+          videos,
+          index
+        })
+      },
+      Err(_) => None
+    },
+    None => None
+  };
+  let template = WatchTemplate {
+    thumbnail: format!("{}/vi/{}/hqdefault.jpg", base_url, video_id),
+    base_url: String::from(&base_url),
+    title: get_str(&video, "title"),
+    author: get_str(&video, "author"),
+    author_id: get_str(&video, "authorId"),
+    description: get_str(&video, "description"),
+    view_count: get_i64(&video, "viewCount"),
+    length: fmt_length(get_i64(&video, "lengthSeconds")),
+    // `t` can be `90` or `90s`
+    start: query.t.as_deref().map(|t| t.trim_end_matches('s').parse::<i64>().unwrap_or(0)).unwrap_or(0),
+    oembed_url: get_oembed_url(&base_url, &video_id),
+    sources,
+    dash_url,
+    playlist,
+    video_id
+  };
+  render(&template, 200)
+}
+
+struct ListVideo {
+  video_id: String,
+  title: String,
+  author: String,
+  length: String,
+  published_text: String
+}
+
+// shorts are played on the normal watch page, keeping any other params (ex: `local`)
+#[get("/shorts/{video_id}")]
+pub async fn shorts_page(req: HttpRequest, path: Path<String>) -> impl Responder {
+  let query_string = req.query_string();
+  let location = if query_string.is_empty() {
+    format!("/watch?v={}", encode(&path.into_inner()))
+  } else {
+    format!("/watch?v={}&{}", encode(&path.into_inner()), query_string)
+  };
+  HttpResponse::Found().insert_header(("Location", location)).finish()
+}
+
+#[derive(Template)]
+#[template(path = "playlist.html")]
+struct PlaylistTemplate {
+  base_url: String,
+  playlist_id: String,
+  title: String,
+  author: String,
+  author_id: String,
+  description: String,
+  thumbnail: String,
+  videos: Vec<ListVideo>
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistPageQueryParams {
+  list: Option<String>,
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>
+}
+
+// local playlists are used over the ones from innertube (same as `/api/v1/playlists`)
+async fn fetch_page_playlist(app_settings: &AppSettings, playlist_id: &str, locale: &ContentLocale) -> Result<Map<String, Value>, (u16, String)> {
+  let db = app_settings.get_json_db().await;
+  if let Some(Value::Object(local_playlist)) = db.seek_for_json("local-playlist", playlist_id).await {
+    return Ok(local_playlist);
+  }
+  let playlist_value = fetch_playlist_with_cache(&db, app_settings, playlist_id, locale).await.map_err(|_| (500, String::from("Failed to fetch playlist")))?;
+  match parse(&playlist_value, &locale.hl) {
+    Ok(playlist) => Ok(playlist.into_inv()),
+    Err(error) => Err((404, error.alerts.first().and_then(|alert| alert.alert_text.clone()).unwrap_or(String::from("Playlist not found"))))
+  }
+}
+
+fn get_list_videos(playlist: &Map<String, Value>) -> Vec<ListVideo> {
+  playlist.get("videos").and_then(|videos| videos.as_array()).unwrap_or(&vec![]).iter().filter_map(|video| {
+    let video = video.as_object()?;
+    Some(ListVideo {
+      video_id: get_str(video, "videoId"),
+      title: get_str(video, "title"),
+      author: get_str(video, "author"),
+      length: fmt_length(get_i64(video, "lengthSeconds")),
+      published_text: String::from("")
+    })
+  }).collect()
+}
+
+#[get("/playlist")]
+pub async fn playlist_page(req: HttpRequest, query: Query<PlaylistPageQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(playlist_id) = query.list.clone() else { return error_page(400, String::from("Missing playlist id `list`")) };
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let base_url = get_base_url(&req, &app_settings);
+  let playlist = match fetch_page_playlist(&app_settings, &playlist_id, &locale).await {
+    Ok(playlist) => playlist,
+    Err((status_code, message)) => return error_page(status_code, message)
+  };
+  let videos = get_list_videos(&playlist);
+  let template = PlaylistTemplate {
+    thumbnail: match videos.first() {
+      Some(video) => format!("{}/vi/{}/hqdefault.jpg", base_url, video.video_id),
+      None => format!("{}/static/icon.png", base_url)
+    },
+    base_url,
+    title: get_str(&playlist, "title"),
+    author: get_str(&playlist, "author"),
+    author_id: get_str(&playlist, "authorId"),
+    description: get_str(&playlist, "description"),
+    videos,
+    playlist_id
+  };
+  render(&template, 200)
+}
+
+#[derive(Template)]
+#[template(path = "channel.html")]
+struct ChannelTemplate {
+  base_url: String,
+  author: String,
+  author_id: String,
+  description: String,
+  sub_count_text: String,
+  thumbnail: String,
+  videos: Vec<ListVideo>
+}
+
+#[derive(Deserialize)]
+pub struct ChannelPageQueryParams {
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>
+}
+
+#[get("/channel/{channel_id}")]
+pub async fn channel_page(req: HttpRequest, path: Path<String>, query: Query<ChannelPageQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let channel_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let db = app_settings.get_json_db().await;
+  let channel_value = match fetch_channel_with_cache(&db, &app_settings, &channel_id, &locale).await {
+    Ok(channel_value) => channel_value,
+    Err(error) => return error_page(404, format!("{}", error))
+  };
+  let Some(channel) = fmt_inv_channel(&channel_value) else { return error_page(404, String::from("Channel not found")) };
+  let videos = channel.get("latestVideos").and_then(|videos| videos.as_array()).unwrap_or(&vec![]).iter().filter_map(|video| {
+    let video = video.as_object()?;
+    Some(ListVideo {
+      video_id: get_str(video, "videoId"),
+      title: get_str(video, "title"),
+      author: get_str(video, "author"),
+      length: fmt_length(get_i64(video, "lengthSeconds")),
+      published_text: get_str(video, "publishedText")
+    })
+  }).collect::<Vec::<ListVideo>>();
+  let template = ChannelTemplate {
+    base_url: get_base_url(&req, &app_settings),
+    author: get_str(&channel, "author"),
+    author_id: get_str(&channel, "authorId"),
+    description: get_str(&channel, "description"),
+    sub_count_text: get_str(&channel, "subCountText"),
+    thumbnail: channel.get("authorThumbnails").and_then(|thumbnails| thumbnails.as_array()).and_then(|thumbnails| thumbnails.last()).and_then(|thumbnail| thumbnail["url"].as_str()).map(String::from).unwrap_or(String::from("/static/icon.png")),
+    videos
+  };
+  render(&template, 200)
+}
+
+struct SearchItem {
+  url: String,
+  thumbnail: String,
+  title: String,
+  subtitle: String
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchTemplate {
+  query: String,
+  page: u32,
+  has_next_page: bool,
+  items: Vec<SearchItem>
+}
+
+#[derive(Deserialize)]
+pub struct SearchPageQueryParams {
+  q: Option<String>,
+  page: Option<u32>,
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>
+}
+
+#[get("/search")]
+pub async fn search_page(req: HttpRequest, query: Query<SearchPageQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let search_query = query.q.clone().unwrap_or(String::from(""));
+  let page = query.page.unwrap_or(1).max(1);
+  if search_query.trim().is_empty() {
+    return render(&SearchTemplate { query: search_query, page, has_next_page: false, items: vec![] }, 200);
+  }
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let db = app_settings.get_json_db().await;
+  let search_value = match fetch_search_with_cache(&db, &app_settings, &search_query, page, &locale).await {
+    Ok(search_value) => search_value,
+    Err(error) => return error_page(500, format!("{}", error))
+  };
+  let items = get_search_results(&search_value).into_iter().filter_map(|item| {
+    match get_str(&item, "type").as_str() {
+      "video" => Some(SearchItem {
+        url: format!("/watch?v={}", get_str(&item, "videoId")),
+        thumbnail: format!("/vi/{}/mqdefault.jpg", get_str(&item, "videoId")),
+        title: get_str(&item, "title"),
+        subtitle: format!("{} · {} · {}", get_str(&item, "author"), fmt_length(get_i64(&item, "lengthSeconds")), get_str(&item, "publishedText"))
+      }),
+      "channel" => Some(SearchItem {
+        url: format!("/channel/{}", get_str(&item, "authorId")),
+        thumbnail: item.get("authorThumbnails").and_then(|thumbnails| thumbnails[0]["url"].as_str()).map(String::from).unwrap_or(String::from("/static/icon.png")),
+        title: get_str(&item, "author"),
+        subtitle: get_str(&item, "subCountText")
+      }),
+      "playlist" => Some(SearchItem {
+        url: format!("/playlist?list={}", get_str(&item, "playlistId")),
+        thumbnail: get_str(&item, "playlistThumbnail"),
+        title: get_str(&item, "title"),
+        subtitle: format!("{} · {} videos", get_str(&item, "author"), get_i64(&item, "videoCount"))
+      }),
+      _ => None
+    }
+  }).collect::<Vec::<SearchItem>>();
+  let has_next_page = get_search_continuation(&search_value).is_some();
+  render(&SearchTemplate { query: search_query, page, has_next_page, items }, 200)
+}
+
+#[derive(Template)]
+#[template(path = "embed.html")]
+struct EmbedTemplate {
+  title: String,
+  thumbnail: String,
+  oembed_url: String,
+  // `start[,end]` (https://www.w3.org/TR/media-frags/#naming-time)
+  media_fragment: String,
+  autoplay: bool,
+  loop_playback: bool,
+  listen: bool,
+  sources: Vec<VideoSource>
+}
+
+#[derive(Deserialize)]
+pub struct EmbedPageQueryParams {
+  start: Option<u64>,
+  end: Option<u64>,
+  autoplay: Option<u8>,
+  #[serde(rename = "loop")]
+  loop_playback: Option<u8>,
+  local: Option<bool>,
+  listen: Option<bool>,
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>
+}
+
+#[get("/embed/{video_id}")]
+pub async fn embed_page(req: HttpRequest, path: Path<String>, query: Query<EmbedPageQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let video_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let base_url = get_base_url(&req, &app_settings);
+  let local = query.local.unwrap_or(app_settings.enable_local_streaming) && app_settings.enable_local_streaming;
+  let listen = query.listen.unwrap_or(false);
+  let player_res = match fetch_player_with_cache(&video_id, &locale, &app_settings, local, Some(&base_url)).await {
+    Ok(player_res) => player_res,
+    Err(error) => return error_page(player_error_status(&error), format!("{}", error))
+  };
+  let video = fmt_inv(&player_res, &locale.hl);
+  let start = query.start.unwrap_or(0);
+  let template = EmbedTemplate {
+    title: get_str(&video, "title"),
+    thumbnail: format!("{}/vi/{}/hqdefault.jpg", base_url, video_id),
+    oembed_url: get_oembed_url(&base_url, &video_id),
+    media_fragment: match query.end {
+      Some(end) => format!("{},{}", start, end),
+      None => format!("{}", start)
+    },
+    autoplay: query.autoplay.unwrap_or(0) == 1,
+    loop_playback: query.loop_playback.unwrap_or(0) == 1,
+    listen,
+    sources: get_video_sources(&video, &video_id, local, &locale, listen)
+  };
+  render(&template, 200)
+}
+
+#[derive(Deserialize)]
+pub struct OEmbedQueryParams {
+  url: String,
+  format: Option<String>,
+  maxwidth: Option<u32>,
+  maxheight: Option<u32>
+}
+
+// https://oembed.com/#section2
+#[get("/oembed")]
+pub async fn oembed(req: HttpRequest, query: Query<OEmbedQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  if query.format.as_deref().unwrap_or("json") != "json" {
+    return HttpResponse::build(StatusCode::from_u16(501).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Only the json format is supported\" }");
+  }
+  let Some(video_id) = video_link_or_id_to_id(&query.url) else {
+    return HttpResponse::build(StatusCode::from_u16(404).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"No video id found in `url`\" }");
+  };
+  let base_url = get_base_url(&req, &app_settings);
+  let player_res = match fetch_player_with_cache(&video_id, &ContentLocale::default(), &app_settings, false, Some(&base_url)).await {
+    Ok(player_res) => player_res,
+    Err(error) => {
+      return HttpResponse::build(StatusCode::from_u16(404).unwrap()).content_type("application/json").body(json!({ "type": "error", "message": "Failed to fetch `player` endpoint", "inner_message": format!("{}", error) }).to_string());
+    }
+  };
+  let video = fmt_inv(&player_res, "en");
+  // keep 16:9 while fitting inside the max dimensions
+  let mut width = query.maxwidth.unwrap_or(560).min(560);
+  let mut height = width * 9 / 16;
+  if let Some(maxheight) = query.maxheight.filter(|maxheight| height > *maxheight) {
+    height = maxheight;
+    width = height * 16 / 9;
+  }
+  let title = get_str(&video, "title");
+  HttpResponse::Ok().content_type("application/json").body(json!({
+    "type": "video",
+    "version": "1.0",
+    "title": title,
+    "author_name": get_str(&video, "author"),
+    "author_url": format!("{}/channel/{}", base_url, get_str(&video, "authorId")),
+    "provider_name": "yaytapi",
+    "provider_url": base_url,
+    "thumbnail_url": format!("{}/vi/{}/hqdefault.jpg", base_url, video_id),
+    "thumbnail_width": 480,
+    "thumbnail_height": 360,
+    "width": width,
+    "height": height,
+    "html": format!("<iframe width=\"{}\" height=\"{}\" src=\"{}/embed/{}\" title=\"{}\" frameborder=\"0\" allow=\"autoplay; fullscreen; picture-in-picture\" allowfullscreen></iframe>", width, height, base_url, video_id, title.replace("&", "&amp;").replace("\"", "&quot;").replace("<", "&lt;").replace(">", "&gt;"))
+  }).to_string())
+}
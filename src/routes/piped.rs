@@ -0,0 +1,234 @@
+use actix_web::web::{Path, Data, Query, ServiceConfig, scope};
+use actix_web::{HttpResponse, HttpRequest, Responder, get};
+use actix_web::http::StatusCode;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, from_str, Value, Map};
+use yayti::parsers::web::{video::{fmt_inv, fmt_inv_with_existing_map}, playlist::parse};
+use crate::helpers::get_previous_data;
+use crate::innertube::{ContentLocale, fetch_suggestions};
+use crate::parsers::chapters::get_chapters;
+use crate::parsers::channel::fmt_inv_channel;
+use crate::parsers::piped::{streams_to_piped, playlist_to_piped, playlist_videos_to_piped, channel_to_piped, search_item_to_piped};
+use crate::parsers::search::{get_search_results, get_search_continuation};
+use crate::parsers::shorts::is_short_player;
+use crate::routes::channel::fetch_channel_with_cache;
+use crate::routes::pages::get_base_url;
+use crate::routes::playlist::{fetch_playlist_with_cache, fetch_continuation_with_cache};
+use crate::routes::search::fetch_search_with_cache;
+use crate::routes::video::{fetch_player_with_cache, fetch_next_with_cache};
+use crate::settings::AppSettings;
+
+// the piped endpoints, mounted under `--piped-prefix` when it's set
+pub fn configure(cfg: &mut ServiceConfig, prefix: Option<&str>) {
+  if let Some(prefix) = prefix {
+    cfg.service(
+      scope(prefix)
+        .service(streams_endpoint)// -> /streams/{video_id}
+        .service(playlist_endpoint)// -> /playlists/{playlist_id}
+        .service(playlist_nextpage_endpoint)// -> /nextpage/playlists/{playlist_id}?nextpage={page}
+        .service(channel_endpoint)// -> /channel/{channel_id}
+        .service(search_endpoint)// -> /search?q={query}
+        .service(search_nextpage_endpoint)// -> /nextpage/search?q={query}&nextpage={page}
+        .service(suggestions_endpoint)// -> /suggestions?query={query}
+    );
+  }
+}
+
+// piped clients look for `error` instead of iv's `type`
+fn piped_error(status_code: u16, error: &str, message: String) -> HttpResponse {
+  HttpResponse::build(StatusCode::from_u16(status_code).unwrap()).content_type("application/json").body(json!({ "error": error, "message": message }).to_string())
+}
+
+fn piped_response(value: &Value) -> HttpResponse {
+  HttpResponse::Ok().content_type("application/json").body(value.to_string())
+}
+
+// piped uses `region` for `gl`, and doesn't send `hl` at all
+#[derive(Deserialize)]
+pub struct PipedQueryParams {
+  hl: Option<String>,
+  region: Option<String>
+}
+
+#[get("/streams/{video_id}")]
+pub async fn streams_endpoint(req: HttpRequest, path: Path<String>, query: Query<PipedQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let video_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), None);
+  let base_url = get_base_url(&req, &app_settings);
+  let player_res = match fetch_player_with_cache(&video_id, &locale, &app_settings, false, Some(&base_url)).await {
+    Ok(player_res) => player_res,
+    Err(error) => return piped_error(500, "Failed to fetch `player` endpoint", format!("{}", error))
+  };
+  let next_res = match fetch_next_with_cache(&video_id, &locale, &app_settings).await {
+    Ok(next_res) => next_res,
+    Err(error) => return piped_error(500, "Failed to fetch `next` endpoint", format!("{}", error))
+  };
+  let mut video = fmt_inv_with_existing_map(&next_res, &locale.hl, fmt_inv(&player_res, &locale.hl));
+  video.insert(String::from("isShort"), json!(is_short_player(&player_res)));
+  piped_response(&streams_to_piped(&video, &get_chapters(&next_res, &player_res), &base_url))
+}
+
+// iv pages are 100 videos long, so there is a next page as long as this one is full
+fn get_playlist_nextpage(playlist: &Map<String, Value>, page: i32) -> Option<String> {
+  if playlist.get("videos").and_then(|videos| videos.as_array()).map(|videos| videos.len()).unwrap_or(0) >= 100 {
+    Some(format!("{}", page + 1))
+  } else {
+    None
+  }
+}
+
+#[get("/playlists/{playlist_id}")]
+pub async fn playlist_endpoint(req: HttpRequest, path: Path<String>, query: Query<PipedQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let playlist_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), None);
+  let base_url = get_base_url(&req, &app_settings);
+  let db = app_settings.get_json_db().await;
+  let playlist = match db.seek_for_json("local-playlist", &playlist_id).await {
+    Some(Value::Object(local_playlist)) => local_playlist,
+    _ => {
+      let playlist_value = match fetch_playlist_with_cache(&db, &app_settings, &playlist_id, &locale).await {
+        Ok(playlist_value) => playlist_value,
+        Err(_) => return piped_error(500, "Failed to fetch playlist", String::from("Failed to fetch playlist"))
+      };
+      match parse(&playlist_value, &locale.hl) {
+        Ok(playlist) => playlist.into_inv(),
+        Err(error) => return piped_error(404, "Playlist not found", error.alerts.first().and_then(|alert| alert.alert_text.clone()).unwrap_or(String::from("Playlist not found")))
+      }
+    }
+  };
+  piped_response(&playlist_to_piped(&playlist, get_playlist_nextpage(&playlist, 1), &base_url))
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistNextpageQueryParams {
+  nextpage: Option<String>,
+  hl: Option<String>,
+  region: Option<String>
+}
+
+#[get("/nextpage/playlists/{playlist_id}")]
+pub async fn playlist_nextpage_endpoint(req: HttpRequest, path: Path<String>, query: Query<PlaylistNextpageQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let playlist_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), None);
+  let base_url = get_base_url(&req, &app_settings);
+  let Some(page) = query.nextpage.as_deref().and_then(|nextpage| nextpage.parse::<i32>().ok()).filter(|page| *page > 0) else {
+    return piped_error(400, "Invalid `nextpage`", String::from("`nextpage` must be the page number from a previous response"));
+  };
+  let db = app_settings.get_json_db().await;
+  let playlist_value = match fetch_continuation_with_cache(&db, &app_settings, &playlist_id, &locale, page).await {
+    Ok(playlist_value) => playlist_value,
+    Err(_) => return piped_error(500, "Failed to fetch playlist", String::from("Failed to fetch playlist continuation"))
+  };
+  let playlist = match parse(&playlist_value, &locale.hl) {
+    Ok(playlist) => playlist.into_inv(),
+    Err(error) => return piped_error(404, "Playlist not found", error.alerts.first().and_then(|alert| alert.alert_text.clone()).unwrap_or(String::from("Playlist not found")))
+  };
+  piped_response(&json!({
+    "nextpage": get_playlist_nextpage(&playlist, page),
+    "relatedStreams": playlist_videos_to_piped(&playlist, &base_url)
+  }))
+}
+
+#[get("/channel/{channel_id}")]
+pub async fn channel_endpoint(req: HttpRequest, path: Path<String>, query: Query<PipedQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let channel_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), None);
+  let db = app_settings.get_json_db().await;
+  let channel_value = match fetch_channel_with_cache(&db, &app_settings, &channel_id, &locale).await {
+    Ok(channel_value) => channel_value,
+    Err(error) => return piped_error(404, "Failed to fetch channel", format!("{}", error))
+  };
+  let Some(channel) = fmt_inv_channel(&channel_value) else {
+    return piped_error(404, "Channel not found", String::from("Channel not found"));
+  };
+  piped_response(&channel_to_piped(&channel, &get_base_url(&req, &app_settings)))
+}
+
+#[derive(Deserialize)]
+pub struct SearchQueryParams {
+  q: Option<String>,
+  // all, videos, channels or playlists (the music_* filters are treated as all)
+  filter: Option<String>,
+  nextpage: Option<String>,
+  hl: Option<String>,
+  region: Option<String>
+}
+
+async fn search(req: &HttpRequest, query: &SearchQueryParams, app_settings: &AppSettings, page: u32) -> HttpResponse {
+  let Some(search_query) = query.q.clone().filter(|q| !q.trim().is_empty()) else {
+    return piped_error(400, "Missing search query", String::from("Missing search query `q`"));
+  };
+  let locale = ContentLocale::from_request(req, query.hl.as_deref(), query.region.as_deref(), None);
+  let base_url = get_base_url(req, app_settings);
+  let db = app_settings.get_json_db().await;
+  let search_value = match fetch_search_with_cache(&db, app_settings, &search_query, page, &locale).await {
+    Ok(search_value) => search_value,
+    Err(error) => return piped_error(500, "Failed to fetch search results", format!("{}", error))
+  };
+  let item_type = match query.filter.as_deref().unwrap_or("all") {
+    "videos" => Some("video"),
+    "channels" => Some("channel"),
+    "playlists" => Some("playlist"),
+    _ => None
+  };
+  let items = get_search_results(&search_value).into_iter().filter(|item| {
+    match item_type {
+      Some(item_type) => item.get("type").and_then(|value| value.as_str()) == Some(item_type),
+      None => true
+    }
+  }).filter_map(|item| search_item_to_piped(&item, &base_url)).collect::<Vec::<Value>>();
+  piped_response(&json!({
+    "items": items,
+    "nextpage": get_search_continuation(&search_value).map(|_| format!("{}", page + 1)),
+    "suggestion": null,
+    "corrected": false
+  }))
+}
+
+#[get("/search")]
+pub async fn search_endpoint(req: HttpRequest, query: Query<SearchQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  search(&req, &query, &app_settings, 1).await
+}
+
+#[get("/nextpage/search")]
+pub async fn search_nextpage_endpoint(req: HttpRequest, query: Query<SearchQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let Some(page) = query.nextpage.as_deref().and_then(|nextpage| nextpage.parse::<u32>().ok()).filter(|page| *page > 0) else {
+    return piped_error(400, "Invalid `nextpage`", String::from("`nextpage` must be the page number from a previous response"));
+  };
+  search(&req, &query, &app_settings, page).await
+}
+
+#[derive(Deserialize)]
+pub struct SuggestionsQueryParams {
+  query: Option<String>,
+  hl: Option<String>,
+  region: Option<String>
+}
+
+#[get("/suggestions")]
+pub async fn suggestions_endpoint(req: HttpRequest, query: Query<SuggestionsQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let search_query = query.query.clone().unwrap_or(String::from(""));
+  if search_query.trim().is_empty() {
+    return piped_response(&json!([]));
+  }
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), None);
+  let db = app_settings.get_json_db().await;
+  let key = format!("{}-{}", search_query, locale.cache_key());
+  // `["query", ["suggestion", ...]]`
+  let suggestions = match get_previous_data("suggestions", &key, &db, &app_settings).await {
+    Some(previous_data) => previous_data["suggestions"].clone(),
+    None => {
+      let suggestions_res = match fetch_suggestions(&app_settings.state.http, &search_query, &locale).await {
+        Ok(suggestions_res) => suggestions_res,
+        Err(error) => return piped_error(500, "Failed to fetch suggestions", format!("{}", error))
+      };
+      let suggestions = from_str::<Value>(&suggestions_res).map(|suggestions| suggestions[1].clone()).unwrap_or(json!([]));
+      if app_settings.cache_requests {
+        db.insert_json("suggestions", &key, &json!({ "suggestions": suggestions, "timestamp": Utc::now().timestamp() })).await;
+      }
+      suggestions
+    }
+  };
+  piped_response(&suggestions)
+}
@@ -1,116 +1,132 @@
 use actix_web::web::{Path, Data, Query};
-use actix_web::{HttpResponse, Responder, get, App};
+use actix_web::{HttpResponse, HttpRequest, Responder, get};
 use chrono::Utc;
 use serde::{Serialize, Deserialize};
 use actix_web::http::StatusCode;
-use serde_json::{from_str,to_string, Value, Map, json, to_string_pretty};
+use serde_json::{from_str,to_string, Value, json, to_string_pretty};
 use yayti::helpers::generate_playlist_continuation;
-use yayti::parsers::ClientContext;
-use yayti::extractors::innertube::{fetch_playlist, fetch_continuation};
 use yayti::parsers::web::playlist::parse;
 use std::str::FromStr;
-use crate::helpers::{get_previous_data, DbWrapper};
+use std::sync::Arc;
+use crate::helpers::DbWrapper;
+use crate::peers::get_previous_data_or_from_peers;
+use crate::circuit::{fetch_upstream, Upstream};
 use crate::AppSettings;
+use crate::innertube::{ContentLocale, fetch_playlist, fetch_continuation};
+use crate::http::HttpError;
 
 #[derive(Serialize, Deserialize)]
 pub struct PlaylistEndpointQueryParams {
   page: Option<String>,
   hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>,
   pretty: Option<u32>
 }
 
+#[derive(Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum FetchPlaylistError {
   FailedToFetchPlaylist,
   FailedToParsePlaylist,
   FailedToGenerateContinuation,
-  FailedToFetchContinuation(reqwest::Error),
-  FailedToParseContinuationResponse(serde_json::Error)
+  FailedToFetchContinuation(Arc<HttpError>),
+  FailedToParseContinuationResponse(Arc<serde_json::Error>)
 }
 
-async fn fetch_continuation_with_cache(db: &DbWrapper, app_settings: &AppSettings, playlist_id: &str, lang: &str, page_num: i32) -> Result<Value, FetchPlaylistError> {
-  match generate_playlist_continuation(&playlist_id, page_num) {
-    Ok(continuation) => {
-      let token = continuation;
-      match get_previous_data("playlist", &format!("{}-{}", token, lang), db, app_settings).await {
-        Some(previous_data) => Ok(previous_data),
-        None => {
-          let continuation = match fetch_continuation("browse", &token, &ClientContext::default_web(), Some(lang)).await {
-            Ok(continuation) => continuation,
-            Err(error) => return Err(FetchPlaylistError::FailedToFetchContinuation(error))
-          };
-          let mut continuation_data = match from_str::<Value>(&continuation) {
-            Ok(result) => result,
-            Err(error) => {
-              return Err(FetchPlaylistError::FailedToParseContinuationResponse(error));
-            }
-          };
-          continuation_data["timestamp"] = Utc::now().timestamp().into();
-          if app_settings.cache_requests {
-            db.insert_json("playlist", &format!("{}-{}", token, lang), &json!(continuation_data)).await;
-          }
-          Ok(continuation_data)
+async fn fetch_continuation_from_cache_or_upstream(db: &DbWrapper, app_settings: &AppSettings, token: &str, locale: &ContentLocale, key: &str) -> Result<Value, FetchPlaylistError> {
+  match get_previous_data_or_from_peers("playlist", key, db, app_settings).await {
+    Some(previous_data) => Ok(previous_data),
+    None => {
+      let continuation = match fetch_upstream("browse", "playlist", key, db, app_settings, fetch_continuation(&app_settings.state.http, "browse", token, locale)).await {
+        Ok(Upstream::Fresh(continuation)) => continuation,
+        Ok(Upstream::Stale(continuation_data)) => return Ok(continuation_data),
+        Err(error) => return Err(FetchPlaylistError::FailedToFetchContinuation(Arc::new(error)))
+      };
+      let mut continuation_data = match from_str::<Value>(&continuation) {
+        Ok(result) => result,
+        Err(error) => {
+          return Err(FetchPlaylistError::FailedToParseContinuationResponse(Arc::new(error)));
         }
+      };
+      continuation_data["timestamp"] = Utc::now().timestamp().into();
+      if app_settings.cache_requests {
+        db.insert_json("playlist", key, &json!(continuation_data)).await;
       }
-    },
-    Err(_) => {
-      return Err(FetchPlaylistError::FailedToGenerateContinuation)
+      Ok(continuation_data)
     }
   }
 }
 
-async fn fetch_playlist_with_cache(db: &DbWrapper, app_settings: &AppSettings, playlist_id: &str, lang: &str) -> Result<Value, FetchPlaylistError> {
-  let previous_data = get_previous_data("playlist", &format!("{}-{}", playlist_id, lang), db, app_settings).await;
+pub async fn fetch_continuation_with_cache(db: &DbWrapper, app_settings: &AppSettings, playlist_id: &str, locale: &ContentLocale, page_num: i32) -> Result<Value, FetchPlaylistError> {
+  match generate_playlist_continuation(playlist_id, page_num) {
+    Ok(continuation) => {
+      let token = continuation;
+      let key = format!("{}-{}", token, locale.cache_key());
+      app_settings.coalesce("playlist", &key, fetch_continuation_from_cache_or_upstream(db, app_settings, &token, locale, &key)).await
+    },
+    Err(_) => Err(FetchPlaylistError::FailedToGenerateContinuation)
+  }
+}
+
+async fn fetch_playlist_from_cache_or_upstream(db: &DbWrapper, app_settings: &AppSettings, playlist_id: &str, locale: &ContentLocale, key: &str) -> Result<Value, FetchPlaylistError> {
+  let previous_data = get_previous_data_or_from_peers("playlist", key, db, app_settings).await;
   match previous_data {
     Some(previous_data) => Ok(previous_data),
     None => {
-      let Ok(playlist) = fetch_playlist(&playlist_id, &ClientContext::default_web(), Some(lang), None).await else { return Err(FetchPlaylistError::FailedToFetchPlaylist) };
+      let playlist = match fetch_upstream("browse", "playlist", key, db, app_settings, fetch_playlist(&app_settings.state.http, playlist_id, locale)).await {
+        Ok(Upstream::Fresh(playlist)) => playlist,
+        Ok(Upstream::Stale(playlist_value)) => return Ok(playlist_value),
+        Err(_) => return Err(FetchPlaylistError::FailedToFetchPlaylist)
+      };
       let Ok(mut playlist_value) = from_str::<Value>(&playlist) else { return Err(FetchPlaylistError::FailedToParsePlaylist) };
       playlist_value["timestamp"] = Utc::now().timestamp().into();
       if app_settings.cache_requests {
-        db.insert_json("playlist", &format!("{}-{}", playlist_id, lang), &json!(playlist_value)).await;
+        db.insert_json("playlist", key, &json!(playlist_value)).await;
       }
       Ok(playlist_value)
     }
   }
 }
 
+pub async fn fetch_playlist_with_cache(db: &DbWrapper, app_settings: &AppSettings, playlist_id: &str, locale: &ContentLocale) -> Result<Value, FetchPlaylistError> {
+  let key = format!("{}-{}", playlist_id, locale.cache_key());
+  app_settings.coalesce("playlist", &key, fetch_playlist_from_cache_or_upstream(db, app_settings, playlist_id, locale, &key)).await
+}
+
 #[get("/api/v1/playlists/{playlist_id}")]
-pub async fn playlist_endpoint(path: Path<String>, query: Query<PlaylistEndpointQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+pub async fn playlist_endpoint(req: HttpRequest, path: Path<String>, query: Query<PlaylistEndpointQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
   let playlist_id = path.into_inner();
   let page = query.page.as_deref();
-  let hl = query.hl.as_deref();
-  let is_pretty = query.pretty.map(|i| if i != 0 { true } else { false }).unwrap_or(false);
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let is_pretty = query.pretty.map(|i| i != 0).unwrap_or(false);
   let db = app_settings.get_json_db().await;
   // if local playlist is available, use it
-  match db.seek_for_json("local-playlist", &playlist_id).await {
-    Some(playlist_data) => {
-      match if is_pretty {
-        to_string_pretty(&playlist_data)
-      } else {
-        to_string(&playlist_data)
-      } {
-        Ok(json_response) => return HttpResponse::build(StatusCode::from_u16(200).unwrap()).content_type("application/json").body(json_response),
-        Err(_) => {
-          return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }");
-        }
+  if let Some(playlist_data) = db.seek_for_json("local-playlist", &playlist_id).await {
+    match if is_pretty {
+      to_string_pretty(&playlist_data)
+    } else {
+      to_string(&playlist_data)
+    } {
+      Ok(json_response) => return HttpResponse::build(StatusCode::from_u16(200).unwrap()).content_type("application/json").body(json_response),
+      Err(_) => {
+        return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }");
       }
-    },
-    None => {}
-  };
-  let mut map = Map::<String, Value>::new();
+    }
+  }
   let playlist_value = match page {
     Some(page) => {
       match i32::from_str(page) {
         Ok(page_num) => {
           if page_num < 1 {
-            return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Page must be greater than zero\" }}"));
+            return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Page must be greater than zero\" }");
           }
-          match fetch_continuation_with_cache(&app_settings.get_json_db().await, &app_settings, &playlist_id, hl.unwrap_or("en"), page_num).await {
+          match fetch_continuation_with_cache(&app_settings.get_json_db().await, &app_settings, &playlist_id, &locale, page_num).await {
             Ok(result) => result,
             Err(error) => {
               match error {
                 FetchPlaylistError::FailedToGenerateContinuation => {
-                  return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Error generating playlist continuation\" }}"));
+                  return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Error generating playlist continuation\" }");
                 },
                 FetchPlaylistError::FailedToFetchContinuation(error) => {
                   return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"{}\" }}", error));
@@ -119,7 +135,7 @@ pub async fn playlist_endpoint(path: Path<String>, query: Query<PlaylistEndpoint
                   return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Error parsing continuation response to JSON\", \"inner_error\": \"{}\" }}", error));
                 },
                 _ => {
-                  return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Unknown error\" }}"));
+                  return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Unknown error\" }");
                 }
               };
             }
@@ -131,22 +147,25 @@ pub async fn playlist_endpoint(path: Path<String>, query: Query<PlaylistEndpoint
       }
     },
     None => {
-      match fetch_playlist_with_cache(&app_settings.get_json_db().await, &app_settings, &playlist_id, &hl.unwrap_or("en")).await { 
+      match fetch_playlist_with_cache(&app_settings.get_json_db().await, &app_settings, &playlist_id, &locale).await { 
         Ok(playlist) => playlist,
         Err(_) => {
-          return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\" }}"));
+          return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\" }");
         }
       }
     }
   };
-  let playlist_result = match parse(&playlist_value, &hl.unwrap_or("en")) {
+  let playlist_result = match parse(&playlist_value, &locale.hl) {
     Ok(playlist_result) => playlist_result,
     Err(error) => {
       let alert = &error.alerts[0];
       return HttpResponse::build(StatusCode::from_u16(404).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"{}\", \"message_type\": \"{}\" }}", alert.alert_text.clone().unwrap_or(String::from("")), alert.alert_type.clone().unwrap_or(String::from(""))));
     }
   };
-  map = playlist_result.into_inv();
+  let mut map = playlist_result.into_inv();
+  if playlist_value["stale"].as_bool().unwrap_or(false) {
+    map.insert(String::from("stale"), json!(true));
+  }
   if app_settings.return_innertube_response {
     map.insert(String::from("innertube"), playlist_value);
   }
@@ -156,8 +175,6 @@ pub async fn playlist_endpoint(path: Path<String>, query: Query<PlaylistEndpoint
     to_string(&map)
   } {
     Ok(json_response) => HttpResponse::build(StatusCode::from_u16(200).unwrap()).content_type("application/json").body(json_response),
-    Err(_) => {
-      return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }");
-    }
+    Err(_) => HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }")
   }
 }
@@ -0,0 +1,53 @@
+use chrono::Utc;
+use serde_json::{from_str, Value};
+use std::fmt::{Formatter, Display};
+use std::sync::Arc;
+use crate::helpers::{get_previous_data, DbWrapper};
+use crate::innertube::{ContentLocale, fetch_search, fetch_continuation};
+use crate::parsers::search::get_search_continuation;
+use crate::settings::AppSettings;
+use crate::http::HttpError;
+
+#[derive(Clone)]
+pub enum FetchSearchError {
+  Reqwest(Arc<HttpError>),
+  FailedToParseSearch(Arc<serde_json::Error>),
+  PageNotFound(u32)
+}
+
+impl Display for FetchSearchError {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+      FetchSearchError::Reqwest(error) => format!("Error making request to innertube {}", error),
+      FetchSearchError::FailedToParseSearch(error) => format!("Failed to parse the `search` response: {}", error),
+      FetchSearchError::PageNotFound(page) => format!("There are no more results after page {}", page - 1)
+    })
+  }
+}
+
+async fn fetch_search_page_with_cache(db: &DbWrapper, app_settings: &AppSettings, key: &str, fetch: impl std::future::Future<Output = Result<String, HttpError>>) -> Result<Value, FetchSearchError> {
+  app_settings.coalesce("search", key, async {
+    match get_previous_data("search", key, db, app_settings).await {
+      Some(previous_data) => Ok(previous_data),
+      None => {
+        let search = fetch.await.map_err(|error| FetchSearchError::Reqwest(Arc::new(error)))?;
+        let mut search_value = from_str::<Value>(&search).map_err(|error| FetchSearchError::FailedToParseSearch(Arc::new(error)))?;
+        search_value["timestamp"] = Utc::now().timestamp().into();
+        if app_settings.cache_requests {
+          db.insert_json("search", key, &search_value).await;
+        }
+        Ok(search_value)
+      }
+    }
+  }).await
+}
+
+// innertube only hands out one page at a time, so each page is found by following the continuations from the first
+pub async fn fetch_search_with_cache(db: &DbWrapper, app_settings: &AppSettings, query: &str, page: u32, locale: &ContentLocale) -> Result<Value, FetchSearchError> {
+  let mut search_value = fetch_search_page_with_cache(db, app_settings, &format!("{}-{}", query, locale.cache_key()), fetch_search(&app_settings.state.http, query, locale)).await?;
+  for current_page in 2..=page {
+    let Some(token) = get_search_continuation(&search_value) else { return Err(FetchSearchError::PageNotFound(current_page)) };
+    search_value = fetch_search_page_with_cache(db, app_settings, &format!("{}-{}", token, locale.cache_key()), fetch_continuation(&app_settings.state.http, "search", &token, locale)).await?;
+  }
+  Ok(search_value)
+}
@@ -0,0 +1,100 @@
+use actix_web::web::{Path, Data, Query};
+use actix_web::{HttpResponse, HttpRequest, Responder, get};
+use actix_web::http::StatusCode;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, from_str, to_string, to_string_pretty, Value};
+use std::fmt::{Formatter, Display};
+use crate::helpers::{get_previous_data, DbWrapper};
+use crate::innertube::{ContentLocale, fetch_reel_item_watch, fetch_reel_watch_sequence};
+use crate::parsers::shorts::{get_sequence, get_sequence_continuation};
+use crate::settings::AppSettings;
+use crate::http::HttpError;
+
+pub enum FetchSequenceError {
+  Reqwest(HttpError),
+  FailedToParseSequence(serde_json::Error),
+  SequenceNotFound
+}
+
+impl Display for FetchSequenceError {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+      FetchSequenceError::Reqwest(error) => format!("Error making request to innertube {}", error),
+      FetchSequenceError::FailedToParseSequence(error) => format!("Failed to parse the `reel` response: {}", error),
+      FetchSequenceError::SequenceNotFound => String::from("No shorts sequence for this video")
+    })
+  }
+}
+
+// without a continuation the sequence starts from the short itself
+pub async fn fetch_sequence_with_cache(db: &DbWrapper, app_settings: &AppSettings, video_id: &str, continuation: Option<&str>, locale: &ContentLocale) -> Result<Value, FetchSequenceError> {
+  let key = format!("{}-{}-{}", video_id, continuation.unwrap_or(""), locale.cache_key());
+  match get_previous_data("shorts-sequence", &key, db, app_settings).await {
+    Some(previous_data) => Ok(previous_data),
+    None => {
+      let sequence_params = match continuation {
+        Some(continuation) => String::from(continuation),
+        None => {
+          let reel = fetch_reel_item_watch(&app_settings.state.http, video_id, locale).await.map_err(FetchSequenceError::Reqwest)?;
+          let reel_value = from_str::<Value>(&reel).map_err(FetchSequenceError::FailedToParseSequence)?;
+          match reel_value["sequenceContinuation"].as_str() {
+            Some(sequence_params) => String::from(sequence_params),
+            None => return Err(FetchSequenceError::SequenceNotFound)
+          }
+        }
+      };
+      let sequence = fetch_reel_watch_sequence(&app_settings.state.http, &sequence_params, locale).await.map_err(FetchSequenceError::Reqwest)?;
+      let mut sequence_value = from_str::<Value>(&sequence).map_err(FetchSequenceError::FailedToParseSequence)?;
+      sequence_value["timestamp"] = Utc::now().timestamp().into();
+      if app_settings.cache_requests {
+        db.insert_json("shorts-sequence", &key, &sequence_value).await;
+      }
+      Ok(sequence_value)
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub struct SequenceEndpointQueryParams {
+  continuation: Option<String>,
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>,
+  pretty: Option<i32>
+}
+
+#[get("/api/v1/shorts/{video_id}/sequence")]
+pub async fn sequence_endpoint(req: HttpRequest, path: Path<String>, query: Query<SequenceEndpointQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let video_id = path.into_inner();
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let db = app_settings.get_json_db().await;
+  let sequence_value = match fetch_sequence_with_cache(&db, &app_settings, &video_id, query.continuation.as_deref(), &locale).await {
+    Ok(sequence_value) => sequence_value,
+    Err(error) => {
+      let status_code = match error {
+        FetchSequenceError::SequenceNotFound => 404,
+        _ => 500
+      };
+      return HttpResponse::build(StatusCode::from_u16(status_code).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Failed to fetch shorts sequence\", \"inner_message\": \"{}\" }}", format!("{}", error).replace("\"", "\\\"")));
+    }
+  };
+  let mut json = json!({
+    "videoId": video_id,
+    "sequence": get_sequence(&sequence_value),
+    "continuation": get_sequence_continuation(&sequence_value)
+  });
+  if app_settings.return_innertube_response {
+    json["innertube"] = sequence_value;
+  }
+  match if query.pretty.unwrap_or(0) == 1 {
+    to_string_pretty(&json)
+  } else {
+    to_string(&json)
+  } {
+    Ok(json_response) => HttpResponse::Ok().content_type("application/json").body(json_response),
+    Err(_) => {
+      HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }")
+    }
+  }
+}
@@ -4,13 +4,11 @@ use serde::{Serialize, Deserialize};
 use chrono::prelude::Utc;
 use actix_web::web::{Path, Data, Query, Payload};
 use actix_web::{HttpResponse, Responder, get, route};
-use yayti::extractors::innertube::fetch_player;
-use yayti::extractors::{ciphers::get_player_js_id, ciphers::get_player_response, innertube::{fetch_next,fetch_player_with_sig_timestamp}};
-use yayti::parsers::{ClientContext, ciphers::{extract_sig_timestamp, decipher_streams}, ciphers, web::video::{fmt_inv_with_existing_map, fmt_inv, get_legacy_formats, get_adaptive_formats}};
+use yayti::parsers::{ciphers::{extract_sig_timestamp, decipher_streams}, ciphers, web::video::{fmt_inv_with_existing_map, fmt_inv, get_legacy_formats, get_adaptive_formats}};
 use yayti::helpers::{generate_yt_video_thumbnail_url,generate_yt_video_thumbnails_within_max_size};
-use reqwest::Client;
 use std::str::FromStr;
 use std::num::ParseIntError;
+use std::sync::Arc;
 use actix_web::http::StatusCode;
 use actix_web::HttpRequest;
 use std::fmt::{Formatter, Display};
@@ -24,100 +22,90 @@ use crate::helpers::get_previous_data;
 use crate::helpers::DbWrapper;
 use crate::helpers::ActixHeadersIntoReqwest;
 use crate::helpers::ReqwestHeadersIntoResponseBuilder;
+use crate::routes::auth::{get_user_token, get_resume_position};
+use crate::innertube::{ContentLocale, InnertubeClient, fetch_player, fetch_next, fetch_player_js};
+use crate::player_js::refresh_player_js;
+use crate::fallback::proxy_to_fallback;
+use crate::circuit::{fetch_upstream, Upstream};
+use crate::peers::get_previous_data_or_from_peers;
+use crate::parsers::chapters::get_chapters;
+use crate::parsers::heatmap::get_heatmap;
+use crate::parsers::annotations::get_annotations;
+use crate::parsers::shorts::is_short_player;
+use crate::http::HttpError;
 
 async fn fetch_player_js_with_cache(db: &DbWrapper, app_settings: &AppSettings, player_js_id_option: Option<String>) -> Result<(String, i32, String), FetchPlayerError> {
-  let player_js_id = match &player_js_id_option {
-    Some (player_js_id) => String::from(player_js_id),
-    None => String::from(match get_player_js_id().await {
-      Ok(player_js_id) => player_js_id,
-      Err(error) => {
-        match error {
-          Some(error) => return Err(FetchPlayerError::Reqwest(error)),
-          None => return Err(FetchPlayerError::PlayerJsIdNotFound)
-        }
-      }
-    })
-  };
-  let need_new_player_js = match &player_js_id_option {
-    Some(_) => true,
-    None => {
-      let previous_js_id = get_previous_data("player", "player_js-id", &db, app_settings).await;
-      match previous_js_id {
-        Some(previous_js_id_str) => {
-          previous_js_id_str.as_str().unwrap_or("") != player_js_id
-        },
-        None => {
-          true
-        }
-      }
+  let current_player_js = app_settings.state.player_js();
+  let player_js = match (player_js_id_option, current_player_js) {
+    // `player_js::spawn_player_js_watcher` keeps the latest player.js around, so this only goes upstream before its first check
+    (None, Some(current_player_js)) => current_player_js,
+    (None, None) => refresh_player_js(db, app_settings).await?,
+    (Some(player_js_id), Some(current_player_js)) if current_player_js.id == player_js_id => current_player_js,
+    // an older player.js (ex: the one a stream was fetched with before a rollout), which isn't cached
+    (Some(player_js_id), _) => {
+      let player_js_response = fetch_player_js(&app_settings.state.http, &player_js_id).await.map_err(|error| FetchPlayerError::Reqwest(Arc::new(error)))?;
+      let signature_timestamp = extract_sig_timestamp(&player_js_response).map_err(FetchPlayerError::SignatureTimestampNotFound)?;
+      return Ok((player_js_response, signature_timestamp, player_js_id));
     }
   };
+  Ok((String::from(&player_js.response), player_js.signature_timestamp, String::from(&player_js.id)))
+}
 
-  
-  if need_new_player_js {
-    let player_js_response = match get_player_response(&player_js_id).await {
-      Ok(player_js_response) => player_js_response,
-      Err(error) => {
-        return Err(FetchPlayerError::Reqwest(error));
-      }
-    };
-    let signature_timestamp = match extract_sig_timestamp(&player_js_response) {
-      Ok(signature_timestamp) => signature_timestamp,
-      Err(error) => {
-        return Err(FetchPlayerError::SignatureTimestampNotFound(error));
-      }
-    };
-
-    match player_js_id_option {
-      Some(_) => {},
-      None => {
-        db.delete("player", "player_js-id").await;
-        db.insert_json("player", "player_js-id", &json!(player_js_id)).await;
-        db.delete("player", &format!("player_js-{}", player_js_id)).await;
-        db.insert_json("player", &format!("player_js-{}", player_js_id), &json!(player_js_response)).await;
-        db.delete("player", "signature_timestamp").await;
-        db.insert_json("player", "signature_timestamp", &json!(signature_timestamp)).await;
-      }
-    };
+#[derive(Clone)]
+pub enum FetchNextError {
+  Reqwest(Arc<HttpError>),
+  FailedToParseNext(Arc<serde_json::Error>)
+}
 
-    Ok((player_js_response, signature_timestamp, player_js_id))
-  } else {
-    let Some(player_js_response) = get_previous_data("player", &format!("player_js-{}", player_js_id), &db, app_settings).await else { todo!() };
-    let Some(signature_timestamp) = get_previous_data("player", "signature_timestamp", &db, app_settings).await else { todo!() };
-    Ok((String::from(player_js_response.as_str().unwrap()), signature_timestamp.as_i64().unwrap() as i32, player_js_id))
+impl Display for FetchNextError {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+      FetchNextError::Reqwest(error) => format!("Error making request to innertube {}", error),
+      FetchNextError::FailedToParseNext(error) => format!("Failed to parse the `next` response: {}", error)
+    })
   }
 }
 
-pub async fn fetch_next_with_cache(id: &str, lang: &str, app_settings: &AppSettings) -> Result<Value, reqwest::Error> {
+async fn fetch_next_from_cache_or_upstream(id: &str, locale: &ContentLocale, app_settings: &AppSettings) -> Result<Value, FetchNextError> {
   // create a connection to the db
   let db = app_settings.get_json_db().await;
-  let previous_data = get_previous_data("next", &format!("{}-{}", id, lang), &db, &app_settings).await;
+  let previous_data = get_previous_data_or_from_peers("next", &format!("{}-{}", id, locale.cache_key()), &db, app_settings).await;
   match previous_data {
     Some(json) => {
       Ok(json)
     },
     None => {
-      match fetch_next(id, &ClientContext::default_web(), Some(lang)).await {
-        Ok(next) => {
-          let Ok(mut json) = from_str::<Value>(&next) else { todo!() };
+      let key = format!("{}-{}", id, locale.cache_key());
+      match fetch_upstream("next", "next", &key, &db, app_settings, fetch_next(&app_settings.state.http, id, locale)).await {
+        Ok(Upstream::Fresh(next)) => {
+          let mut json = from_str::<Value>(&next).map_err(|error| FetchNextError::FailedToParseNext(Arc::new(error)))?;
           json["timestamp"] = Utc::now().timestamp().into();
           if app_settings.cache_requests {
-            db.insert_json("next", &format!("{}-{}", id, lang), &json).await;
+            db.insert_json("next", &key, &json).await;
           }
           Ok(json)
         },
-        Err(error) => Err(error)
+        Ok(Upstream::Stale(json)) => Ok(json),
+        Err(error) => Err(FetchNextError::Reqwest(Arc::new(error)))
       }
     }
   }
 }
+
+pub async fn fetch_next_with_cache(id: &str, locale: &ContentLocale, app_settings: &AppSettings) -> Result<Value, FetchNextError> {
+  app_settings.coalesce("next", &format!("{}-{}", id, locale.cache_key()), fetch_next_from_cache_or_upstream(id, locale, app_settings)).await
+}
+
+#[derive(Clone)]
 pub enum FetchPlayerError {
-  Reqwest(reqwest::Error),
+  Reqwest(Arc<HttpError>),
   PlayerJsIdNotFound,
   SignatureTimestampNotFound(ParseIntError),
   FailedToSerializePlayer,
-  ResponseUnplayable,
-  LoginRequired,
+  // the video can't be played, with the reason parsed out of `playabilityStatus` and innertube's message for it
+  Unplayable(UnplayableReason, String),
+  // the video isn't available in the requested region (the allowed regions are included when innertube gives them)
+  GeoBlocked(String, Vec<String>),
   FailedToDecipher(String)
 }
 
@@ -125,189 +113,336 @@ impl Display for FetchPlayerError {
   fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
     write!(f, "{}", match self {
       FetchPlayerError::Reqwest(error) => format!("Error making request to innertube {}", error),
-      FetchPlayerError::PlayerJsIdNotFound => format!("No player.js id found in `/iframe_api` response"),
-      FetchPlayerError::SignatureTimestampNotFound(_) => format!("Unable to parse sig timestamp from player.js response"),
-      FetchPlayerError::FailedToSerializePlayer => format!("Failed to serialize the JSON response from innertube (this probably means the response was the wrong mime type)"),
-      FetchPlayerError::ResponseUnplayable => format!("Response is unplayable"),
-      FetchPlayerError::LoginRequired => format!("Login required"),
+      FetchPlayerError::PlayerJsIdNotFound => String::from("No player.js id found in `/iframe_api` response"),
+      FetchPlayerError::SignatureTimestampNotFound(error) => format!("Unable to parse sig timestamp from player.js response: {}", error),
+      FetchPlayerError::FailedToSerializePlayer => String::from("Failed to serialize the JSON response from innertube (this probably means the response was the wrong mime type)"),
+      FetchPlayerError::Unplayable(reason, message) => if message.is_empty() {
+        String::from(reason.default_message())
+      } else {
+        String::from(message)
+      },
+      FetchPlayerError::GeoBlocked(region, _) => format!("This video is not available in the requested region: {}", region),
       FetchPlayerError::FailedToDecipher(error) => format!("Failed to decipher: {}", error)
     })
   }
 }
 
-pub async fn fetch_player_with_cache(id: &str, lang: &str, app_settings: &AppSettings, local: bool, hostname: Option<&str>) -> Result<Value,FetchPlayerError> {
+#[derive(Clone, Copy, PartialEq)]
+pub enum UnplayableReason {
+  AgeRestricted,
+  MembersOnly,
+  Private,
+  Removed,
+  GeoBlocked,
+  Premiere,
+  // anything else innertube wants a login for (ex: bot checks)
+  LoginRequired,
+  Unplayable
+}
+
+impl UnplayableReason {
+  // the machine readable `reason` in error responses
+  pub fn as_str(&self) -> &str {
+    match self {
+      UnplayableReason::AgeRestricted => "age_restricted",
+      UnplayableReason::MembersOnly => "members_only",
+      UnplayableReason::Private => "private",
+      UnplayableReason::Removed => "removed",
+      UnplayableReason::GeoBlocked => "geo_blocked",
+      UnplayableReason::Premiere => "premiere",
+      UnplayableReason::LoginRequired => "login_required",
+      UnplayableReason::Unplayable => "unplayable"
+    }
+  }
+  fn default_message(&self) -> &str {
+    match self {
+      UnplayableReason::AgeRestricted => "This video is age restricted",
+      UnplayableReason::MembersOnly => "This video is only available to channel members",
+      UnplayableReason::Private => "This video is private",
+      UnplayableReason::Removed => "This video has been removed",
+      UnplayableReason::GeoBlocked => "This video is not available in the requested region",
+      UnplayableReason::Premiere => "This video hasn't premiered yet",
+      UnplayableReason::LoginRequired => "Login required",
+      UnplayableReason::Unplayable => "Response is unplayable"
+    }
+  }
+  pub fn status_code(&self) -> u16 {
+    match self {
+      UnplayableReason::AgeRestricted | UnplayableReason::MembersOnly | UnplayableReason::Private | UnplayableReason::LoginRequired => 403,//🤷‍♀️ this might not be the best response code
+      UnplayableReason::GeoBlocked => 451,
+      UnplayableReason::Removed | UnplayableReason::Premiere | UnplayableReason::Unplayable => 404
+    }
+  }
+}
+
+// innertube's messages are in the requested language, so only the status and the structured parts of the response are checked
+// (private videos only say so in the message, so they're reported as `login_required` unless `videoDetails` is there to say otherwise)
+fn get_unplayable_reason(json: &Value) -> UnplayableReason {
+  let playability_status = &json["playabilityStatus"];
+  let status = playability_status["status"].as_str().unwrap_or("");
+  let error_screen = &playability_status["errorScreen"];
+  if status == "AGE_CHECK_REQUIRED" || status == "AGE_VERIFICATION_REQUIRED" || playability_status["desktopLegacyAgeGateReason"].is_number() {
+    UnplayableReason::AgeRestricted
+  } else if playability_status["liveStreamability"].is_object() {
+    UnplayableReason::Premiere
+  } else if error_screen["playerLegacyDesktopYpcOfferRenderer"].is_object() || error_screen["ypcTrailerRenderer"].is_object() {
+    UnplayableReason::MembersOnly
+  } else if json["videoDetails"]["isPrivate"].as_bool().unwrap_or(false) {
+    UnplayableReason::Private
+  } else if status == "ERROR" {
+    UnplayableReason::Removed
+  } else if status == "LOGIN_REQUIRED" {
+    UnplayableReason::LoginRequired
+  } else {
+    UnplayableReason::Unplayable
+  }
+}
+
+fn check_playability(json: &Value, locale: &ContentLocale) -> Result<(), FetchPlayerError> {
+  match json["playabilityStatus"]["status"].as_str() {
+    Some(status) => {
+      // upcoming premieres and streams are returned as normal videos
+      if status == "OK" || status == "LIVE_STREAM_OFFLINE" {
+        return Ok(());
+      }
+      let reason = json["playabilityStatus"]["reason"].as_str().unwrap_or("");
+      // `reason` is in the requested language, so geo-blocks are only detected from the regions the video is available in
+      // (the clients without a microformat fall through to the next client, which usually has one)
+      let allowed_regions = json["microformat"]["playerMicroformatRenderer"]["availableCountries"].as_array().map(|regions| {
+        regions.iter().filter_map(|region| region.as_str().map(String::from)).collect::<Vec::<String>>()
+      }).unwrap_or(vec![]);
+      if !allowed_regions.is_empty() && !allowed_regions.contains(&locale.gl) {
+        return Err(FetchPlayerError::GeoBlocked(String::from(&locale.gl), allowed_regions));
+      }
+      match status {
+        // `UNPLAYABLE` is mostly embedding being disabled, which other clients don't care about
+        "LOGIN_REQUIRED" | "AGE_CHECK_REQUIRED" | "AGE_VERIFICATION_REQUIRED" | "ERROR" | "UNPLAYABLE" => {
+          Err(FetchPlayerError::Unplayable(get_unplayable_reason(json), String::from(reason)))
+        },
+        _ => Ok(())
+      }
+    },
+    None => Ok(())
+  }
+}
+
+// videos that can't be played get the reason's status, and innertube failing gets a 502
+pub fn player_error_status(error: &FetchPlayerError) -> u16 {
+  match error {
+    FetchPlayerError::GeoBlocked(_, _) => 451,
+    FetchPlayerError::Unplayable(reason, _) => reason.status_code(),
+    FetchPlayerError::Reqwest(_) => 502,
+    _ => 500
+  }
+}
+
+// the error response for a failed `/player` fetch, with a machine readable `reason` when the video itself can't be played
+pub fn player_error_response(error: &FetchPlayerError) -> HttpResponse {
+  match error {
+    FetchPlayerError::GeoBlocked(region, allowed_regions) => {
+      HttpResponse::build(StatusCode::from_u16(451).unwrap()).content_type("application/json").body(json!({
+        "type": "error",
+        "message": "This video is not available in the requested region",
+        "reason": UnplayableReason::GeoBlocked.as_str(),
+        "region": region,
+        "allowedRegions": allowed_regions
+      }).to_string())
+    },
+    FetchPlayerError::Unplayable(reason, _) => {
+      HttpResponse::build(StatusCode::from_u16(reason.status_code()).unwrap()).content_type("application/json").body(json!({
+        "type": "error",
+        "message": format!("{}", error),
+        "reason": reason.as_str()
+      }).to_string())
+    },
+    _ => HttpResponse::build(StatusCode::from_u16(player_error_status(error)).unwrap()).content_type("application/json").body(json!({
+      "type": "error",
+      "message": "Failed to fetch `player` endpoint",
+      "inner_message": format!("{}", error)
+    }).to_string())
+  }
+}
+
+// when an upcoming premiere or stream is scheduled to start
+pub fn get_premiere_timestamp(json: &Value) -> Option<i64> {
+  if json["playabilityStatus"]["status"].as_str() != Some("LIVE_STREAM_OFFLINE") {
+    return None;
+  }
+  let scheduled_start_time = &json["playabilityStatus"]["liveStreamability"]["liveStreamabilityRenderer"]["offlineSlate"]["liveStreamOfflineSlateRenderer"]["scheduledStartTime"];
+  scheduled_start_time.as_str().and_then(|timestamp| timestamp.parse::<i64>().ok()).or(scheduled_start_time.as_i64())
+}
+
+pub fn player_cache_key(id: &str, locale: &ContentLocale, local: bool) -> String {
+  format!("{}-{}-{}", id, locale.cache_key(), local)
+}
+
+// a client later in `--player-clients` might be able to play what this one can't (ex: age restricted or embed disabled videos)
+fn should_try_next_client(error: &FetchPlayerError) -> bool {
+  matches!(error, FetchPlayerError::Unplayable(UnplayableReason::AgeRestricted | UnplayableReason::LoginRequired | UnplayableReason::Unplayable, _))
+}
+
+// the embedded players skip the age gate for most videos, so they're tried when every `--player-clients` client hit it
+const AGE_GATE_BYPASS_CLIENTS: [InnertubeClient; 2] = [InnertubeClient::TvEmbedded, InnertubeClient::WebEmbedded];
+
+async fn fetch_player_with_client(id: &str, client: &InnertubeClient, locale: &ContentLocale, app_settings: &AppSettings, db: &DbWrapper, local: bool, hostname: &str) -> Result<Value,FetchPlayerError> {
+  // only the browser clients need player.js (for the signature timestamp and deciphering)
+  let player_js = if client.needs_deciphering() {
+    Some(fetch_player_js_with_cache(db, app_settings, None).await?)
+  } else {
+    None
+  };
+  let signature_timestamp = player_js.as_ref().map(|(_, signature_timestamp, _)| *signature_timestamp);
+  // googlevideo only serves streams to the ip that asked for them, so locally proxied streams have to go out the same way
+  let egress = app_settings.state.http.pick_egress();
+  let egress_param = egress.query_params();
+  let player = match fetch_player(&app_settings.state.http, egress, id, signature_timestamp, client, locale).await {
+    Ok(player) => player,
+    Err(error) => return Err(FetchPlayerError::Reqwest(Arc::new(error)))
+  };
+  let mut json = match from_str::<Value>(&player) {
+    Ok(json) => json,
+    Err(_) => {
+      return Err(FetchPlayerError::FailedToSerializePlayer);
+    }
+  };
+  check_playability(&json, locale)?;
+  // there are no streams until the scheduled start, so the cached response shouldn't outlive it
+  if let Some(premiere_timestamp) = get_premiere_timestamp(&json) {
+    json["expires"] = premiere_timestamp.into();
+  }
+  let mut streams = Vec::<String>::new();
+  let empty_vec = Vec::new();
+  let formats = match json["streamingData"]["formats"].as_array() {
+    Some(formats) => formats,
+    None => &empty_vec
+  };
+  let adaptive_formats = match json["streamingData"]["adaptiveFormats"].as_array() {
+    Some(formats) => formats,
+    None => &empty_vec
+  };
+  let need_to_decipher = formats.first().or(adaptive_formats.first()).is_some_and(|format| format["url"].as_str().is_none());
+  if need_to_decipher {
+    // the app clients get plain urls, so only the browser clients should ever need to decipher
+    let Some((player_js_response, _, player_js_id)) = &player_js else {
+      return Err(FetchPlayerError::FailedToDecipher(format!("{} returned ciphered streams", client.name())));
+    };
+    for format in formats.iter().chain(adaptive_formats) {
+      streams.push(String::from(format["signatureCipher"].as_str().unwrap()));
+    }
+    let deciphered_streams = if app_settings.decipher_on_video_endpoint {
+      match decipher_streams(streams, player_js_response) {
+        Ok(streams) => streams,
+        Err(error) => {
+          return Err(FetchPlayerError::FailedToDecipher(error));
+        }
+      }
+    } else {
+      streams.into_iter().map(|stream| {
+        Some(format!("{}/decipher_stream?signature_cipher={}&player_js_id={}&video_id={}&local={}&hl={}&gl={}{}", hostname, encode(&stream), player_js_id, &id, local && app_settings.enable_local_streaming, encode(&locale.hl), encode(&locale.gl), egress_param))
+      }).collect::<Vec::<Option<String>>>()
+    };
+    let formats_len = formats.len();
+    let adaptive_len = adaptive_formats.len();
+    let mut i = 0;
+    for k in 0..formats_len {
+      json["streamingData"]["formats"][k]["url"] = json!(deciphered_streams[i]);
+      i += 1;
+    }
+    for k in 0..adaptive_len {
+      json["streamingData"]["adaptiveFormats"][k]["url"] = json!(deciphered_streams[i]);
+      i += 1;
+    }
+  } else {
+    let formats_len = formats.len();
+    let adaptive_len = adaptive_formats.len();
+    fn get_stream_url(url: &str, hostname: &str, is_local: bool, egress_param: &str) -> String{
+      let url_parts = url.split("googlevideo.com").collect::<Vec::<&str>>();
+      let google_hostname = format!("{}googlevideo.com", url_parts[0]).replace("https://", "");
+      let url_after = url_parts[1];
+      if is_local {
+        format!("{}{}&host={}{}", hostname, url_after, encode(&google_hostname), egress_param)
+      } else {// iv always includes host param in response
+        format!("https://{}{}&host={}", google_hostname, url_after, encode(&google_hostname))
+      }
+    }
+    for k in 0..formats_len {
+      let url = json["streamingData"]["formats"][k]["url"].as_str().unwrap_or("");
+      json["streamingData"]["formats"][k]["url"] = json!(get_stream_url(url, hostname, local && app_settings.enable_local_streaming, &egress_param));
+    }
+    for k in 0..adaptive_len {
+      let url = json["streamingData"]["adaptiveFormats"][k]["url"].as_str().unwrap_or("");
+      json["streamingData"]["adaptiveFormats"][k]["url"] = json!(get_stream_url(url, hostname, local && app_settings.enable_local_streaming, &egress_param));
+    }
+  }
+  json["client"] = json!(client.name());
+  json["timestamp"] = Utc::now().timestamp().into();
+  if app_settings.cache_requests {
+    db.insert_json("player", &player_cache_key(id, locale, local), &json).await;
+  }
+  Ok(json)
+}
+
+async fn fetch_player_from_cache_or_upstream(id: &str, locale: &ContentLocale, app_settings: &AppSettings, local: bool, hostname: Option<&str>) -> Result<Value,FetchPlayerError> {
   let hostname = app_settings.clone().pub_url.unwrap_or(String::from(hostname.unwrap_or("")));
   let db = app_settings.get_json_db().await;
-  let previous_data = get_previous_data("player", &format!("{}-{}-{}", id, lang, local), &db, app_settings).await;
+  // player responses aren't shared with peers (see `peers::SHARED_COLLECTIONS`)
+  let previous_data = get_previous_data("player", &player_cache_key(id, locale, local), &db, app_settings).await;
   match previous_data {
     Some(json) => {
       Ok(json)
     },
     None => {
-      if app_settings.use_android_endpoint_for_streams {
-        let context = ClientContext::default_android();
-        match fetch_player(id, &context, Some(lang)).await {
-          Ok(player) => {
-            let json = match from_str::<Value>(&player) {
-              Ok(json) => json,
-              Err(_) => {
-                return Err(FetchPlayerError::FailedToSerializePlayer);
-              }
-            };
-            match json["playabilityStatus"]["status"].as_str() {
-              Some(status) => {
-                if status == "LOGIN_REQUIRED" {
-                  return Err(FetchPlayerError::LoginRequired);
-                }
-                if status == "ERROR" {
-                  return Err(FetchPlayerError::ResponseUnplayable);
-                }
-              },
-              None => {}
-            };
-            Ok(json)
+      let mut last_error = FetchPlayerError::Unplayable(UnplayableReason::Unplayable, String::from(""));
+      for client in &app_settings.player_clients {
+        match fetch_player_with_client(id, client, locale, app_settings, &db, local, &hostname).await {
+          Ok(json) => {
+            app_settings.state.record_player_client(client);
+            return Ok(json);
           },
-          Err(error) => Err(FetchPlayerError::Reqwest(error))
-        }
-      } else {
-        let context = ClientContext::default_web();
-        let (player_js_response, signature_timestamp, player_js_id) = match fetch_player_js_with_cache(&db, &app_settings, None).await {
-          Ok(response) => response,
           Err(error) => {
-            return Err(error);
-          }
-        };
-  
-        match fetch_player_with_sig_timestamp(id, signature_timestamp, &context, Some(lang)).await {
-          Ok(player) => {
-            let mut json = match from_str::<Value>(&player) {
-              Ok(json) => json,
-              Err(_) => {
-                return Err(FetchPlayerError::FailedToSerializePlayer);
-              }
-            };
-            match json["playabilityStatus"]["status"].as_str() {
-              Some(status) => {
-                if status == "LOGIN_REQUIRED" {
-                  return Err(FetchPlayerError::LoginRequired);
-                }
-                if status == "ERROR" {
-                  return Err(FetchPlayerError::ResponseUnplayable);
-                }
-              },
-              None => {}
-            };
-            let mut streams = Vec::<String>::new();
-            let empty_vec = Vec::new();
-            let formats = match json["streamingData"]["formats"].as_array() {
-              Some(formats) => formats,
-              None => &empty_vec
-            };
-            let adaptive_formats = match json["streamingData"]["adaptiveFormats"].as_array() {
-              Some(formats) => formats,
-              None => &empty_vec
-            };
-            let need_to_decipher = if formats.len() > 0 {
-              match formats[0]["url"].as_str() {
-                Some(_) => false,
-                None => true
-              }
-            } else if adaptive_formats.len() > 0 {
-              match adaptive_formats[0]["url"].as_str() {
-                Some(_) => false,
-                None => true
-              }
-            } else {
-              false
-            };
-            if need_to_decipher {
-              for k in 0..formats.len() {
-                streams.push(String::from(formats[k]["signatureCipher"].as_str().unwrap()));
-              }
-              for k in 0..adaptive_formats.len() {
-                streams.push(String::from(adaptive_formats[k]["signatureCipher"].as_str().unwrap()));
-              }
-              let deciphered_streams = if app_settings.decipher_on_video_endpoint {
-                match decipher_streams(streams, &player_js_response) {
-                  Ok(streams) => streams,
-                  Err(error) => {
-                    return Err(FetchPlayerError::FailedToDecipher(error));
-                  }
-                }
-              } else {
-                streams.into_iter().map(|stream| {
-                  Some(format!("{}/decipher_stream?signature_cipher={}&player_js_id={}&video_id={}&local={}", hostname, encode(&stream), &player_js_id, &id, local && app_settings.enable_local_streaming))
-                }).collect::<Vec::<Option<String>>>()
-              };
-              let formats_len = formats.len();
-              let adaptive_len = adaptive_formats.len();
-              let mut i = 0;
-              for k in 0..formats_len {
-                json["streamingData"]["formats"][k]["url"] = json!(deciphered_streams[i]);
-                i = i + 1;
-              }
-              for k in 0..adaptive_len {
-                json["streamingData"]["adaptiveFormats"][k]["url"] = json!(deciphered_streams[i]);
-                i = i + 1;
-              }
-            } else {
-              let formats_len = formats.len();
-              let adaptive_len = adaptive_formats.len();
-              let mut i = 0;
-              fn get_stream_url(url: &str, hostname: &str, is_local: bool) -> String{
-                let url_parts = url.split("googlevideo.com").collect::<Vec::<&str>>();
-                let google_hostname = format!("{}googlevideo.com", url_parts[0]).replace("https://", "");
-                let url_after = url_parts[1];
-                if is_local {
-                  format!("{}{}&host={}", hostname, url_after, encode(&google_hostname))
-                } else {// iv always includes host param in response
-                  format!("https://{}{}&host={}", google_hostname, url_after, encode(&google_hostname))
-                }
-              }
-              for k in 0..formats_len {
-                let url = json["streamingData"]["formats"][k]["url"].as_str().unwrap_or("");
-                json["streamingData"]["formats"][k]["url"] = json!(get_stream_url(url, &hostname, local && app_settings.enable_local_streaming));
-                i = i + 1;
-              }
-              for k in 0..adaptive_len {
-                let url = json["streamingData"]["adaptiveFormats"][k]["url"].as_str().unwrap_or("");
-                json["streamingData"]["adaptiveFormats"][k]["url"] = json!(get_stream_url(url, &hostname, local && app_settings.enable_local_streaming));
-                i = i + 1;
-              }
-            }
-            json["timestamp"] = Utc::now().timestamp().into();
-            if app_settings.cache_requests {
-              db.insert_json("player", &format!("{}-{}-{}", id, lang, local), &json).await;
+            if !should_try_next_client(&error) {
+              return Err(error);
             }
-            Ok(json.clone())
-          },
-          Err(error) => Err(FetchPlayerError::Reqwest(error))
-        }  
+            log::info!("{} couldn't play {} ({}), trying the next client", client.name(), id, error);
+            last_error = error;
+          }
+        }
+      }
+      if app_settings.bypass_age_gate && matches!(last_error, FetchPlayerError::Unplayable(UnplayableReason::AgeRestricted, _)) {
+        for client in AGE_GATE_BYPASS_CLIENTS.iter().filter(|client| !app_settings.player_clients.contains(client)) {
+          match fetch_player_with_client(id, client, locale, app_settings, &db, local, &hostname).await {
+            Ok(json) => {
+              app_settings.state.record_player_client(client);
+              return Ok(json);
+            },
+            // the age restriction is still the reason it can't be played
+            Err(error) => log::info!("{} couldn't bypass the age gate on {} ({})", client.name(), id, error)
+          }
+        }
       }
+      Err(last_error)
     }
   }
 }
 
-const DEFAULT_FIELDS: [&str; 37] = ["type", "title", "videoId", "videoThumbnails", "storyboards", "description", "descriptionHtml", "published", "publishedText", "keywords", "viewCount", "likeCount", "dislikeCount", "paid", "premium", "isFamilyFriendly", "allowedRegions", "genre", "genreUrl", "author", "authorId", "authorUrl", "authorThumbnails", "subCountText", "lengthSeconds", "allowRatings", "rating", "isListed", "liveNow", "isUpcoming", "hlsUrl", "dashUrl", "adaptiveFormats", "formatStreams", "captions", "recommendedVideos", "musicTracks"];
+pub async fn fetch_player_with_cache(id: &str, locale: &ContentLocale, app_settings: &AppSettings, local: bool, hostname: Option<&str>) -> Result<Value,FetchPlayerError> {
+  app_settings.coalesce("player", &player_cache_key(id, locale, local), fetch_player_from_cache_or_upstream(id, locale, app_settings, local, hostname)).await
+}
+
+const DEFAULT_FIELDS: [&str; 39] = ["type", "title", "videoId", "videoThumbnails", "storyboards", "description", "descriptionHtml", "published", "publishedText", "keywords", "viewCount", "likeCount", "dislikeCount", "paid", "premium", "isFamilyFriendly", "allowedRegions", "genre", "genreUrl", "author", "authorId", "authorUrl", "authorThumbnails", "subCountText", "lengthSeconds", "allowRatings", "rating", "isListed", "liveNow", "isUpcoming", "premiereTimestamp", "hlsUrl", "dashUrl", "adaptiveFormats", "formatStreams", "captions", "recommendedVideos", "musicTracks", "isShort"];
 
 trait AreFieldsInValue {
-  fn are_all_fields_in_value(&self, fields: &Vec::<String>) -> bool;
+  fn are_all_fields_in_value(&self, fields: &[String]) -> bool;
 }
 impl AreFieldsInValue for Map<String, Value> {
-  fn are_all_fields_in_value(&self, fields: &Vec::<String>) -> bool {
-    for i in 0..fields.len() {
-      if !self.contains_key(&fields[i]) {
-        return false;
-      }
-    }
-    true
+  fn are_all_fields_in_value(&self, fields: &[String]) -> bool {
+    fields.iter().all(|field| self.contains_key(field))
   }
 }
 
-fn filter_out_everything_but_fields(mut json: Map<String, Value>, fields: &Vec::<String>) -> Map<String, Value> {
-  let keys = json.keys().into_iter().map(|s| String::from(s)).collect::<Vec::<String>>();
-  for i in 0..keys.len() {
-    let key = &keys[i];
+fn filter_out_everything_but_fields(mut json: Map<String, Value>, fields: &[String]) -> Map<String, Value> {
+  let keys = json.keys().map(String::from).collect::<Vec::<String>>();
+  for key in &keys {
     if !fields.contains(key) {
       json.remove(key);
     }
@@ -315,8 +450,8 @@ fn filter_out_everything_but_fields(mut json: Map<String, Value>, fields: &Vec::
   json
 }
 
-fn add_in_missing_fields(mut json: Map<String, Value>, fields: &Vec::<String>) -> Map<String, Value> {
-  let keys = json.keys().into_iter().map(|s| String::from(s)).collect::<Vec::<String>>();
+fn add_in_missing_fields(mut json: Map<String, Value>, fields: &[String]) -> Map<String, Value> {
+  let keys = json.keys().map(String::from).collect::<Vec::<String>>();
   for field in fields {
     if !keys.contains(field) {
       json.insert(String::from(field), json!(None::<String>));
@@ -325,11 +460,10 @@ fn add_in_missing_fields(mut json: Map<String, Value>, fields: &Vec::<String>) -
   json
 }
 
-fn sort_to_inv_schema(json: Map<String, Value>, fields: &Vec::<String>) -> Map<String, Value> {
+fn sort_to_inv_schema(json: Map<String, Value>, fields: &[String]) -> Map<String, Value> {
   let mut output = Map::<String, Value>::new();
-  let keys = json.keys().into_iter().map(|s| String::from(s)).collect::<Vec::<String>>();
-  for i in 0..fields.len() {
-    let field = &fields[i];
+  let keys = json.keys().map(String::from).collect::<Vec::<String>>();
+  for field in fields {
     if keys.contains(field) {
       output.insert(String::from(field), json[field].clone());
     }
@@ -340,6 +474,8 @@ fn sort_to_inv_schema(json: Map<String, Value>, fields: &Vec::<String>) -> Map<S
 #[derive(Deserialize)]
 pub struct VideoEndpointQueryParams {
   hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>,
   local: Option<bool>,
   fields: Option<String>,
   pretty: Option<i32>
@@ -361,10 +497,11 @@ pub struct CommentUrl {
 #[get("/api/v1/videos/{video_id}")]
 pub async fn video_endpoint(req: HttpRequest, path: Path<String>, query: Query<VideoEndpointQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
   let local = query.local.unwrap_or(false);
-  let connection_info = req.connection_info();
-  let uri = String::from(format!("{}://{}", connection_info.scheme(), connection_info.host()));
+  let connection_info = req.connection_info().clone();
+  let uri = format!("{}://{}", connection_info.scheme(), connection_info.host());
   let video_id = path.into_inner();
-  let lang = query.hl.clone().unwrap_or(String::from("en"));
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let lang = String::from(&locale.hl);
   let is_pretty = match query.pretty {
     Some(pretty) => pretty == 1,
     None => false
@@ -372,22 +509,21 @@ pub async fn video_endpoint(req: HttpRequest, path: Path<String>, query: Query<V
   let fields = match &query.fields {
     Some(fields) => {
       if fields.contains(",") {
-        fields.split(",").into_iter().map(|field| String::from(field)).collect::<Vec::<String>>()
+        fields.split(",").map(String::from).collect::<Vec::<String>>()
       } else {
         vec!(String::from(fields))
       }
     },
-    None => DEFAULT_FIELDS.into_iter().map(|string| String::from(string)).collect::<Vec::<String>>()
+    None => DEFAULT_FIELDS.into_iter().map(String::from).collect::<Vec::<String>>()
   };
-  let player_res = match fetch_player_with_cache(&video_id, &lang, &app_settings, local, Some(&uri)).await {
+  let player_res = match fetch_player_with_cache(&video_id, &locale, &app_settings, local, Some(&uri)).await {
     Ok(player_res) => player_res,
     Err(fetch_player_error) => {
-      let status_code = match fetch_player_error {
-        FetchPlayerError::LoginRequired => 403,//🤷‍♀️ this might not be the best response code
-        FetchPlayerError::ResponseUnplayable => 404,
-        _ => 500
-      };
-      return HttpResponse::build(StatusCode::from_u16(status_code).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Failed to fetch `player` endpoint\", \"inner_message\": \"{}\" }}", fetch_player_error));
+      // the fallback instance might still be able to play it
+      if let Some(response) = proxy_to_fallback(&req, &app_settings).await {
+        return response;
+      }
+      return player_error_response(&fetch_player_error);
     }
   };
   let mut innertube = InnerTubeResponse {
@@ -395,21 +531,41 @@ pub async fn video_endpoint(req: HttpRequest, path: Path<String>, query: Query<V
     player: player_res.clone()
   };
   let mut json = fmt_inv(&player_res, &lang);
+  json.insert(String::from("isShort"), json!(is_short_player(&player_res)));
+  let mut is_stale = false;
   if !json.are_all_fields_in_value(&fields) {
-    let Ok(next_res) = fetch_next_with_cache(&video_id, &lang, &app_settings).await else { todo!() };
+    // with the circuit for `next` open and nothing cached to fall back on, there's no video to return
+    let next_res = match fetch_next_with_cache(&video_id, &locale, &app_settings).await {
+      Ok(next_res) => next_res,
+      Err(error) => {
+        return HttpResponse::build(StatusCode::from_u16(502).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Failed to fetch `next` endpoint\", \"inner_message\": \"{}\" }}", format!("{}", error).replace("\"", "\\\"")));
+      }
+    };
     json = fmt_inv_with_existing_map(&next_res, &lang, json);
+    is_stale = next_res["stale"].as_bool().unwrap_or(false);
+    // fields yayti doesn't know about yet
+    if fields.contains(&String::from("chapters")) {
+      json.insert(String::from("chapters"), get_chapters(&next_res, &player_res));
+    }
+    if fields.contains(&String::from("heatmap")) {
+      json.insert(String::from("heatmap"), get_heatmap(&next_res));
+    }
     innertube.next = Some(next_res);
   }
+  if let Some(premiere_timestamp) = get_premiere_timestamp(&player_res) {
+    json.insert(String::from("isUpcoming"), json!(true));
+    json.insert(String::from("premiereTimestamp"), json!(premiere_timestamp));
+  }
   json = filter_out_everything_but_fields(json, &fields);
   // 🔍 figure out what thumbnail sizes exist
-  let client = Client::new();
-  let has_maxres = match client.head(format!("https://i.ytimg.com/vi/{}/maxresdefault.jpg", video_id)).send().await {
+  let http = &app_settings.state.http;
+  let has_maxres = match http.send(http.client.head(format!("https://i.ytimg.com/vi/{}/maxresdefault.jpg", video_id))).await {
     Ok(res) => {
       res.status() != 404
     },
     Err(_) => false
   };
-  let has_sd = has_maxres || match client.head(format!("https://i.ytimg.com/vi/{}/sddefault.jpg", video_id)).send().await {
+  let has_sd = has_maxres || match http.send(http.client.head(format!("https://i.ytimg.com/vi/{}/sddefault.jpg", video_id))).await {
     Ok(res) => {
       res.status() != 404
     },
@@ -449,18 +605,24 @@ pub async fn video_endpoint(req: HttpRequest, path: Path<String>, query: Query<V
   if app_settings.sort_to_inv_schema {
     json = sort_to_inv_schema(json, &fields);
   }
+  // added after filtering and sorting, which both drop keys iv doesn't have
+  if is_stale {
+    json.insert(String::from("stale"), json!(true));
+  }
   // TODO integrate comments into fields API
-  match &innertube.next.as_ref() {
-    Some(next) => {
-      json.insert(String::from("comments"), json!(yayti::parsers::web::video::get_comment_continuations(next).unwrap_or(vec!()).into_iter().map(|continuation| {
-        CommentUrl {
-          title: format!("{}", continuation.title),
-          url: format!("/api/v1/comments/{}?continuation={}", video_id, continuation.token),
-          token: format!("{}", continuation.token)
-        }
-      }).collect::<Vec::<CommentUrl>>()));
-    },
-    None => {}
+  if let Some(next) = &innertube.next {
+    json.insert(String::from("comments"), json!(yayti::parsers::web::video::get_comment_continuations(next).unwrap_or(vec!()).into_iter().map(|continuation| {
+      CommentUrl {
+        title: continuation.title.to_string(),
+        url: format!("/api/v1/comments/{}?continuation={}", video_id, continuation.token),
+        token: continuation.token.to_string()
+      }
+    }).collect::<Vec::<CommentUrl>>()));
+  }
+  // where the authenticated user left off
+  if let Some(token) = get_user_token(&req, &app_settings) {
+    let db = app_settings.get_json_db().await;
+    json.insert(String::from("resume_at"), json!(get_resume_position(&db, &token, &video_id).await.unwrap_or(0)));
   }
   if app_settings.return_innertube_response {
     json.insert(String::from("innertube"), json!(innertube));
@@ -478,12 +640,51 @@ pub async fn video_endpoint(req: HttpRequest, path: Path<String>, query: Query<V
   HttpResponse::Ok().content_type("application/json").body(json_response)
 }
 
+#[derive(Deserialize)]
+pub struct AnnotationsQueryParams {
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>,
+  pretty: Option<i32>
+}
+
+// iv's annotations are gone, this returns the cards and end screen elements from the cached player response instead
+#[get("/api/v1/annotations/{video_id}")]
+pub async fn annotations_endpoint(req: HttpRequest, path: Path<String>, query: Query<AnnotationsQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
+  let video_id = path.into_inner();
+  let connection_info = req.connection_info().clone();
+  let uri = format!("{}://{}", connection_info.scheme(), connection_info.host());
+  let locale = ContentLocale::from_request(&req, query.hl.as_deref(), query.region.as_deref(), query.gl.as_deref());
+  let player_res = match fetch_player_with_cache(&video_id, &locale, &app_settings, false, Some(&uri)).await {
+    Ok(player_res) => player_res,
+    Err(fetch_player_error) => {
+      // the fallback instance might still be able to play it
+      if let Some(response) = proxy_to_fallback(&req, &app_settings).await {
+        return response;
+      }
+      return player_error_response(&fetch_player_error);
+    }
+  };
+  let json = get_annotations(&player_res);
+  let json_response = match if query.pretty.unwrap_or(0) == 1 {
+    to_string_pretty(&json)
+  } else {
+    to_string(&json)
+  } {
+    Ok(json_response) => json_response,
+    Err(_) => {
+      return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"failed to serialize response\" }");
+    }
+  };
+  HttpResponse::Ok().content_type("application/json").body(json_response)
+}
+
 #[get("/vi/{video_id}/{file_name}.jpg")]
-pub async fn video_thumbnail_proxy(params: Path<(String, String)>) -> impl Responder {
+pub async fn video_thumbnail_proxy(params: Path<(String, String)>, app_settings: Data<AppSettings>) -> impl Responder {
   let video_id = String::from(&params.0);
   let file_name = String::from(&params.1);
-  let client = Client::new();
-  match client.get(generate_yt_video_thumbnail_url(&video_id, &file_name)).send().await {
+  let http = &app_settings.state.http;
+  match http.send(http.client.get(generate_yt_video_thumbnail_url(&video_id, &file_name))).await {
     Ok(thumbnail_response) => {
       HttpResponse::Ok().content_type("image/jpeg").status(thumbnail_response.status()).streaming(thumbnail_response.bytes_stream())
     },
@@ -496,7 +697,9 @@ pub struct LatestVersionQueryParams {
   id: String,
   itag: String,
   local: Option<bool>,
-  hl: Option<String>
+  hl: Option<String>,
+  region: Option<String>,
+  gl: Option<String>
 }
 
 struct Format {
@@ -506,41 +709,39 @@ struct Format {
 #[get("/latest_version")]
 pub async fn latest_version(req: HttpRequest, params: Query<LatestVersionQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
   let video_id = &params.id;
-  let connection_info = req.connection_info();
-  let uri = String::from(format!("{}://{}", connection_info.scheme(), connection_info.host()));
+  let connection_info = req.connection_info().clone();
+  let uri = format!("{}://{}", connection_info.scheme(), connection_info.host());
   let itag = i32::from_str(&params.itag).unwrap_or(0); 
-  let lang = params.hl.clone().unwrap_or(String::from("en"));
+  let locale = ContentLocale::from_request(&req, params.hl.as_deref(), params.region.as_deref(), params.gl.as_deref());
   let local = &params.local.unwrap_or(false);
-  let player_res = match fetch_player_with_cache(&video_id, &lang, &app_settings, *local, Some(&uri)).await {
+  let player_res = match fetch_player_with_cache(video_id, &locale, &app_settings, *local, Some(&uri)).await {
     Ok(player_res) => player_res,
     Err(error) => {
-      return HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Failed to fetch `/player` endpoint\", \"inner_message\": \"{}\" }}", error))
+      // the fallback instance might still be able to play it
+      if let Some(response) = proxy_to_fallback(&req, &app_settings).await {
+        return response;
+      }
+      return player_error_response(&error);
     }
   };
-  let legacy_formats = match get_legacy_formats(&player_res) {
-    Some(legacy_formats) => legacy_formats,
-    None => Vec::new()
-  };
+  let legacy_formats = get_legacy_formats(&player_res).unwrap_or_default();
   let mut format = None;
   let mut available_itags = Vec::<i32>::new();
-  for i in 0..legacy_formats.len() {
-    available_itags.push(legacy_formats[i].itag);
-    if legacy_formats[i].itag == itag {
+  for legacy_format in &legacy_formats {
+    available_itags.push(legacy_format.itag);
+    if legacy_format.itag == itag {
       format = Some(Format {
-        url: legacy_formats[i].url.clone()
+        url: legacy_format.url.clone()
       });
       break;
     }
   }
-  let adaptive_formats = match get_adaptive_formats(&player_res) {
-    Some(adaptive_formats) => adaptive_formats,
-    None => Vec::new()
-  };
-  for i in 0..adaptive_formats.len() {
-    available_itags.push(adaptive_formats[i].itag);
-    if adaptive_formats[i].itag == itag {
+  let adaptive_formats = get_adaptive_formats(&player_res).unwrap_or_default();
+  for adaptive_format in &adaptive_formats {
+    available_itags.push(adaptive_format.itag);
+    if adaptive_format.itag == itag {
       format = Some(Format {
-        url: adaptive_formats[i].url.clone()
+        url: adaptive_format.url.clone()
       });
       break;
     }
@@ -564,14 +765,17 @@ pub async fn latest_version(req: HttpRequest, params: Query<LatestVersionQueryPa
 #[derive(Deserialize)]
 pub struct VideoPlaybackQueryParams {
   host: String,
-  local: Option<bool>
+  local: Option<bool>,
+  // the proxy and source address that fetched the player response this stream is from
+  egress: Option<usize>,
+  source: Option<String>
 }
 
 #[route("/videoplayback", method="GET", method="HEAD")]
 pub async fn videoplayback(req: HttpRequest, mut payload: Payload, params: Query<VideoPlaybackQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
   let local = &params.local.unwrap_or(true);
   if !app_settings.enable_local_streaming && *local {
-    return HttpResponse::build(StatusCode::from_u16(403).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Local streaming is disabled.\" }}"));
+    return HttpResponse::build(StatusCode::from_u16(403).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Local streaming is disabled.\" }");
   }
   let hostname = match decode(&params.host) {
     Ok(hostname) => format!("https://{}", hostname),
@@ -603,15 +807,17 @@ pub async fn videoplayback(req: HttpRequest, mut payload: Payload, params: Query
       }
     });
 
-    let client = Client::new();
-    let forwarded_req = client
+    let http = &app_settings.state.http;
+    let forwarded_req = http.egress_client(&http.egress_from_query(params.egress, params.source.as_deref()))
         .request(req.method().into(), uri)
         .body(reqwest::Body::wrap_stream(UnboundedReceiverStream::new(rx)));
   
-    let res = forwarded_req
-        .send()
-        .await
-        .unwrap();
+    let res = match http.send_stream(forwarded_req).await {
+      Ok(res) => res,
+      Err(error) => {
+        return HttpResponse::build(StatusCode::from_u16(502).unwrap()).content_type("application/json").body(json!({ "type": "error", "message": "Failed to reach googlevideo", "inner_message": format!("{}", error) }).to_string());
+      }
+    };
   
     let client_resp = HttpResponse::build(res.status());
     res.headers().add_headers_to_builder(client_resp).streaming(res.bytes_stream())
@@ -625,12 +831,21 @@ pub struct DecipherStreamQueryParams {
   signature_cipher: String,
   player_js_id: String,
   video_id: String,
-  local: Option<bool>
+  local: Option<bool>,
+  hl: Option<String>,
+  gl: Option<String>,
+  egress: Option<usize>,
+  source: Option<String>
 }
 
 #[get("/decipher_stream")]
 pub async fn decipher_stream(params: Query<DecipherStreamQueryParams>, app_settings: Data<AppSettings>) -> impl Responder {
   let local = params.local.unwrap_or(false);
+  let default_locale = ContentLocale::default();
+  let locale = ContentLocale {
+    hl: params.hl.clone().unwrap_or(default_locale.hl),
+    gl: params.gl.clone().unwrap_or(default_locale.gl)
+  };
   
   if app_settings.decipher_streams {
     let signature_cipher = match decode(&params.signature_cipher) {
@@ -643,7 +858,7 @@ pub async fn decipher_stream(params: Query<DecipherStreamQueryParams>, app_setti
     let input_blacklist = ["\"", "'", ";", "function", "for", "while", "(", "{", "[", "]", "}", ")"];
     for item in input_blacklist {
       if signature_cipher.contains(item) {
-        return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Refusing to execute potentially malicious payload\" }}"))
+        return HttpResponse::build(StatusCode::from_u16(400).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Refusing to execute potentially malicious payload\" }")
       }
     }
     let db = app_settings.get_json_db().await;
@@ -655,8 +870,9 @@ pub async fn decipher_stream(params: Query<DecipherStreamQueryParams>, app_setti
     };
     match ciphers::decipher_stream(&signature_cipher, &player_js_res) {
       Ok(deciphered_url) => {
-        let client = Client::new();
-        let is_decipher_good = match client.head(&deciphered_url).send().await {
+        let http = &app_settings.state.http;
+        let egress = http.egress_from_query(params.egress, params.source.as_deref());
+        let is_decipher_good = match http.send(http.egress_client(&egress).head(&deciphered_url)).await {
           Ok(response) => response.status() != 403,
           Err(_) => false
         };
@@ -664,15 +880,15 @@ pub async fn decipher_stream(params: Query<DecipherStreamQueryParams>, app_setti
           let url_parts = deciphered_url.split("googlevideo.com").collect::<Vec::<&str>>();
           let google_hostname = format!("{}googlevideo.com", url_parts[0]).replace("https://", "");
           let url_after = url_parts[1];
-          let url = format!("{}&host={}&local={}", url_after, encode(&google_hostname), local);
+          let url = format!("{}&host={}&local={}{}", url_after, encode(&google_hostname), local, egress.query_params());
           HttpResponse::build(StatusCode::from_u16(302).unwrap()).insert_header(("Location",url)).content_type("application/json").body("")
         } else {
-          db.delete("player", &format!("{}-{}-{}", &params.video_id, "en", local)).await;
+          db.delete("player", &player_cache_key(&params.video_id, &locale, local)).await;
           HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"Deciphering JS ran without fail, but the result was a broken link.\", \"url\": \"{}\" }}", &deciphered_url))
         }
       },
       Err(error) => {
-        db.delete("player", &format!("{}-{}-{}", &params.video_id, "en", local)).await;
+        db.delete("player", &player_cache_key(&params.video_id, &locale, local)).await;
         HttpResponse::build(StatusCode::from_u16(500).unwrap()).content_type("application/json").body(format!("{{ \"type\": \"error\", \"message\": \"{}\" }}", error.replace("\"", "\\\"")))
       }
     }
@@ -681,4 +897,169 @@ pub async fn decipher_stream(params: Query<DecipherStreamQueryParams>, app_setti
     HttpResponse::build(StatusCode::from_u16(403).unwrap()).content_type("application/json").body("{ \"type\": \"error\", \"message\": \"Deciphering streams has been disabled.\" }")
   }
 }
- 
\ No newline at end of file
+ 
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+  use std::sync::Arc;
+  use actix_web::App;
+  use actix_web::test::{init_service, call_service, read_body_json, TestRequest};
+  use actix_web::web::Data;
+  use serde_json::{json, Value};
+  use crate::innertube::ContentLocale;
+  use crate::player_js::PlayerJs;
+  use crate::settings::AppSettings;
+  use super::{fetch_player_with_cache, check_playability, video_endpoint, FetchPlayerError, UnplayableReason};
+
+  // innertube responses in the format `--record-innertube` writes them in
+  const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/innertube");
+  const VIDEO_ID: &str = "jNQXAC9IVRw";
+  const HOSTNAME: &str = "http://127.0.0.1:8080";
+
+  // replays the fixtures instead of going upstream, without a cache so every request reaches innertube
+  fn replay_settings(player_clients: &str) -> AppSettings {
+    let args = vec![
+      String::from("yaytapi"),
+      format!("--replay-innertube={}", FIXTURES),
+      format!("--player-clients={}", player_clients),
+      format!("--db-name={}", std::env::temp_dir().join("yaytapi-test.db").display()),
+      String::from("--enable-local-streaming"),
+      String::from("--no-cache")
+    ];
+    let app_settings = AppSettings::from_cli_args(&args);
+    // the player.js watcher isn't running, and the fixtures don't need deciphering
+    app_settings.state.swap_player_js(Arc::new(PlayerJs {
+      id: String::from("fixture"),
+      response: String::new(),
+      signature_timestamp: 19804,
+      updated_at: 0
+    }));
+    app_settings
+  }
+
+  fn keys(value: &Value) -> BTreeSet<String> {
+    value.as_object().map(|map| map.keys().cloned().collect()).unwrap_or_default()
+  }
+
+  fn stream_urls(player: &Value) -> Vec<String> {
+    ["formats", "adaptiveFormats"].iter().flat_map(|formats| {
+      player["streamingData"][formats].as_array().cloned().unwrap_or_default()
+    }).map(|format| String::from(format["url"].as_str().unwrap_or(""))).collect()
+  }
+
+  #[actix_web::test]
+  async fn android_and_web_player_paths_have_the_same_shape() {
+    for local in [true, false] {
+      let locale = ContentLocale::default();
+      let Ok(web) = fetch_player_with_cache(VIDEO_ID, &locale, &replay_settings("web"), local, Some(HOSTNAME)).await else { panic!("the web player failed") };
+      let Ok(android) = fetch_player_with_cache(VIDEO_ID, &locale, &replay_settings("android"), local, Some(HOSTNAME)).await else { panic!("the android player failed") };
+      assert_eq!(web["client"], "web");
+      assert_eq!(android["client"], "android");
+      for player in [&web, &android] {
+        assert!(player["timestamp"].is_i64());
+        let urls = stream_urls(player);
+        assert!(!urls.is_empty());
+        for url in urls {
+          let expected_prefix = if local { format!("{}/videoplayback?", HOSTNAME) } else { String::from("https://rr5---sn-ab5l6nrz.googlevideo.com/videoplayback?") };
+          assert!(url.starts_with(&expected_prefix), "{} doesn't start with {}", url, expected_prefix);
+          assert!(url.ends_with("&host=rr5---sn-ab5l6nrz.googlevideo.com"), "{} is missing `host`", url);
+        }
+      }
+      assert_eq!(keys(&web["streamingData"]), keys(&android["streamingData"]));
+      for formats in ["formats", "adaptiveFormats"] {
+        let web_formats = web["streamingData"][formats].as_array().cloned().unwrap_or_default();
+        let android_formats = android["streamingData"][formats].as_array().cloned().unwrap_or_default();
+        assert_eq!(web_formats.len(), android_formats.len());
+        for (web_format, android_format) in web_formats.iter().zip(android_formats.iter()) {
+          assert_eq!(keys(web_format), keys(android_format));
+        }
+      }
+    }
+  }
+
+  #[actix_web::test]
+  async fn video_endpoint_combines_the_player_and_next_responses() {
+    let app = init_service(App::new().app_data(Data::new(replay_settings("web"))).service(video_endpoint)).await;
+    let req = TestRequest::get().uri(&format!("/api/v1/videos/{}?local=true", VIDEO_ID)).insert_header(("Host", "127.0.0.1:8080")).to_request();
+    let res = call_service(&app, req).await;
+    assert_eq!(res.status(), 200);
+    let video: Value = read_body_json(res).await;
+    assert_eq!(video["videoId"], VIDEO_ID);
+    assert_eq!(video["title"], "Me at the zoo");
+    assert_eq!(video["author"], "jawed");
+    assert_eq!(video["authorId"], "UC4QobU6STFB0P71PMvOGN5A");
+    assert_eq!(video["lengthSeconds"], 19);
+    assert_eq!(video["isShort"], false);
+    assert!(video.get("stale").is_none());
+    // from the player response
+    let adaptive_formats = video["adaptiveFormats"].as_array().cloned().unwrap_or_default();
+    assert!(!adaptive_formats.is_empty());
+    for format in adaptive_formats {
+      let url = format["url"].as_str().unwrap_or("");
+      assert!(url.starts_with(&format!("{}/videoplayback?", HOSTNAME)), "{} isn't proxied", url);
+    }
+    // from the next response
+    let recommended_video_ids = video["recommendedVideos"].as_array().cloned().unwrap_or_default().iter().map(|video| String::from(video["videoId"].as_str().unwrap_or(""))).collect::<Vec<String>>();
+    assert_eq!(recommended_video_ids, vec!["LeAltgu_pbM", "hPzNl6NKAG0"]);
+    assert!(video["comments"].is_array());
+  }
+
+  #[actix_web::test]
+  async fn video_endpoint_reports_removed_videos() {
+    let app = init_service(App::new().app_data(Data::new(replay_settings("web"))).service(video_endpoint)).await;
+    let req = TestRequest::get().uri("/api/v1/videos/aaaaaaaaaaa").to_request();
+    let res = call_service(&app, req).await;
+    assert_eq!(res.status(), 404);
+    let error: Value = read_body_json(res).await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["reason"], "removed");
+  }
+
+  #[test]
+  fn detects_geo_blocks_in_any_language() {
+    let locale = ContentLocale { hl: String::from("de"), gl: String::from("DE") };
+    let player = json!({
+      "playabilityStatus": { "status": "UNPLAYABLE", "reason": "Der Uploader hat dieses Video in deinem Land nicht verfügbar gemacht" },
+      "microformat": { "playerMicroformatRenderer": { "availableCountries": ["CA", "US"] } }
+    });
+    match check_playability(&player, &locale) {
+      Err(FetchPlayerError::GeoBlocked(region, allowed_regions)) => {
+        assert_eq!(region, "DE");
+        assert_eq!(allowed_regions, vec!["CA", "US"]);
+      },
+      _ => panic!("not detected as geo-blocked")
+    }
+  }
+
+  #[test]
+  fn classifies_unplayable_videos_in_any_language() {
+    let locale = ContentLocale { hl: String::from("de"), gl: String::from("US") };
+    let reason = |player: Value| match check_playability(&player, &locale) {
+      Err(FetchPlayerError::Unplayable(reason, _)) => reason.as_str().to_string(),
+      _ => String::from("playable")
+    };
+    assert_eq!(reason(json!({ "playabilityStatus": { "status": "LOGIN_REQUIRED", "reason": "Melde dich an, um dein Alter zu bestätigen", "desktopLegacyAgeGateReason": 1 } })), "age_restricted");
+    assert_eq!(reason(json!({ "playabilityStatus": { "status": "UNPLAYABLE", "reason": "Nur für Kanalmitglieder", "errorScreen": { "playerLegacyDesktopYpcOfferRenderer": {} } } })), "members_only");
+    assert_eq!(reason(json!({ "playabilityStatus": { "status": "UNPLAYABLE", "reason": "Premiere in 2 Stunden", "liveStreamability": {} } })), "premiere");
+    assert_eq!(reason(json!({ "playabilityStatus": { "status": "LOGIN_REQUIRED", "reason": "Privates Video" }, "videoDetails": { "isPrivate": true } })), "private");
+    assert_eq!(reason(json!({ "playabilityStatus": { "status": "ERROR", "reason": "Dieses Video ist nicht mehr verfügbar" } })), "removed");
+    // english words in the message don't change anything
+    assert_eq!(reason(json!({ "playabilityStatus": { "status": "LOGIN_REQUIRED", "reason": "This video is private, members only, and age-restricted" } })), "login_required");
+  }
+
+  #[test]
+  fn doesnt_mistake_other_errors_for_geo_blocks() {
+    let locale = ContentLocale::default();
+    // english text about a country, but the requested region is allowed
+    let player = json!({
+      "playabilityStatus": { "status": "UNPLAYABLE", "reason": "Playback on other websites has been disabled by the video owner (in this country)" },
+      "microformat": { "playerMicroformatRenderer": { "availableCountries": ["CA", "US"] } }
+    });
+    assert!(matches!(check_playability(&player, &locale), Err(FetchPlayerError::Unplayable(UnplayableReason::Unplayable, _))));
+    let player = json!({
+      "playabilityStatus": { "status": "OK" },
+      "microformat": { "playerMicroformatRenderer": { "availableCountries": ["CA"] } }
+    });
+    assert!(check_playability(&player, &locale).is_ok());
+  }
+}
@@ -0,0 +1,205 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use chrono::Utc;
+use regex::Regex;
+use reqwest::StatusCode;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, from_value};
+use tokio::sync::Notify;
+use crate::http::{Egress, HttpClient, HttpError, random_u64};
+use crate::settings::AppSettings;
+
+// only accepts the necessary cookies, so eu ips get the homepage instead of the consent page
+const CONSENT_COOKIE: &str = "SOCS=CAI";
+
+// how often the pool is topped up and saved when nothing has been retired
+const SESSION_KEEPER_INTERVAL: Duration = Duration::from_secs(300);
+
+// what innertube knows a visitor by between requests
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Session {
+  pub id: String,
+  #[serde(rename = "visitorData")]
+  pub visitor_data: String,
+  // the consent cookie and whatever cookies the homepage set along with the visitor data
+  pub cookies: String,
+  // the web client version the homepage was served with (newer than the hardcoded one)
+  #[serde(rename = "clientVersion")]
+  pub client_version: Option<String>,
+  #[serde(rename = "createdAt")]
+  pub created_at: i64,
+  pub requests: u64,
+  // the proxy and source address the session was created from, innertube ties the visitor data to that ip (sessions saved before this went out directly)
+  #[serde(default)]
+  pub egress: Egress
+}
+
+// a session without its visitor data and cookies, for `/api/v1/stats`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionStats {
+  pub id: String,
+  #[serde(rename = "clientVersion")]
+  pub client_version: Option<String>,
+  #[serde(rename = "createdAt")]
+  pub created_at: i64,
+  pub requests: u64
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionPoolStats {
+  pub sessions: Vec<SessionStats>,
+  // sessions that got a bot check and were replaced
+  pub retired: u64
+}
+
+#[derive(Default)]
+pub struct SessionPool {
+  sessions: Mutex<Vec<Session>>,
+  next: AtomicUsize,
+  retired: AtomicU64,
+  // wakes the keeper up to replace a retired session right away
+  replenish: Notify
+}
+
+impl SessionPool {
+  // round-robin through the sessions created from `egress`, requests go out with a bare context when there are none
+  pub fn pick(&self, egress: &Egress) -> Option<Session> {
+    let mut sessions = self.sessions.lock().unwrap();
+    let matching = sessions.iter().enumerate().filter(|(_, session)| session.egress == *egress).map(|(index, _)| index).collect::<Vec::<usize>>();
+    if matching.is_empty() {
+      return None;
+    }
+    let index = matching[self.next.fetch_add(1, Ordering::Relaxed) % matching.len()];
+    sessions[index].requests += 1;
+    Some(sessions[index].clone())
+  }
+  pub fn retire(&self, id: &str) {
+    let mut sessions = self.sessions.lock().unwrap();
+    if let Some(index) = sessions.iter().position(|session| session.id == id) {
+      let session = sessions.remove(index);
+      log::warn!("Retiring innertube session {} after a bot check ({} requests)", session.id, session.requests);
+      self.retired.fetch_add(1, Ordering::Relaxed);
+      self.replenish.notify_one();
+    }
+  }
+  fn len(&self) -> usize {
+    self.sessions.lock().unwrap().len()
+  }
+  fn add(&self, session: Session) {
+    self.sessions.lock().unwrap().push(session);
+  }
+  fn sessions(&self) -> Vec<Session> {
+    self.sessions.lock().unwrap().clone()
+  }
+  pub fn stats(&self) -> SessionPoolStats {
+    SessionPoolStats {
+      sessions: self.sessions.lock().unwrap().iter().map(|session| SessionStats {
+        id: String::from(&session.id),
+        client_version: session.client_version.clone(),
+        created_at: session.created_at,
+        requests: session.requests
+      }).collect(),
+      retired: self.retired.load(Ordering::Relaxed)
+    }
+  }
+}
+
+// `Sign in to confirm you’re not a bot` comes back as a `LOGIN_REQUIRED` playability status (and sometimes a 429)
+pub fn is_bot_check(status: StatusCode, response: &str) -> bool {
+  status == StatusCode::TOO_MANY_REQUESTS || response.contains("confirm you’re not a bot") || response.contains("confirm you're not a bot")
+}
+
+// a fresh visitor from the homepage's `ytcfg`, fetched through `egress`
+async fn create_session(http: &HttpClient, egress: Egress) -> Result<Option<Session>, HttpError> {
+  let homepage_response = http.send(http.egress_client(&egress).get(format!("{}/", http.innertube_url)).header("Cookie", CONSENT_COOKIE)).await?;
+  let mut cookies = vec![String::from(CONSENT_COOKIE)];
+  for set_cookie in homepage_response.headers().get_all("set-cookie") {
+    // ex: `VISITOR_INFO1_LIVE=abc; Domain=.youtube.com; Path=/` -> `VISITOR_INFO1_LIVE=abc`
+    if let Some(cookie) = set_cookie.to_str().ok().and_then(|set_cookie| set_cookie.split(';').next()) {
+      cookies.push(String::from(cookie.trim()));
+    }
+  }
+  let homepage = homepage_response.text().await?;
+  let Ok(visitor_data_re) = Regex::new(r#""VISITOR_DATA":"([^"]+)""#) else { todo!() };
+  let Ok(client_version_re) = Regex::new(r#""INNERTUBE_CLIENT_VERSION":"([^"]+)""#) else { todo!() };
+  let Some(visitor_data_captures) = visitor_data_re.captures(&homepage) else { return Ok(None) };
+  Ok(Some(Session {
+    id: format!("{:016x}", random_u64()),
+    visitor_data: String::from(visitor_data_captures.get(1).unwrap().as_str()),
+    cookies: cookies.join("; "),
+    client_version: client_version_re.captures(&homepage).map(|client_version_captures| String::from(client_version_captures.get(1).unwrap().as_str())),
+    created_at: Utc::now().timestamp(),
+    requests: 0,
+    egress
+  }))
+}
+
+// loads the saved sessions at startup, then keeps `--innertube-sessions` of them around (replacing retired ones right away) and saves them
+pub fn spawn_session_keeper(app_settings: AppSettings) {
+  if app_settings.innertube_sessions == 0 {
+    return;
+  }
+  tokio::spawn(async move {
+    let http = &app_settings.state.http;
+    if let Some(sessions) = app_settings.get_json_db().await.seek_for_json("sessions", "pool").await.and_then(|sessions| from_value::<Vec<Session>>(sessions).ok()) {
+      // the ones from a proxy or source address that isn't configured anymore would be used from the wrong ip
+      let sessions = sessions.into_iter().filter(|session| http.is_egress_configured(&session.egress)).take(app_settings.innertube_sessions).collect::<Vec::<Session>>();
+      log::info!("Loaded {} innertube sessions", sessions.len());
+      for session in sessions {
+        http.sessions.add(session);
+      }
+    }
+    loop {
+      while http.sessions.len() < app_settings.innertube_sessions {
+        // spread across the proxies and source addresses the same way requests are
+        match create_session(http, http.pick_egress()).await {
+          Ok(Some(session)) => {
+            log::info!("Created innertube session {}", session.id);
+            http.sessions.add(session);
+          },
+          Ok(None) => {
+            log::warn!("Failed to create an innertube session: no visitor data on the homepage");
+            break;
+          },
+          Err(error) => {
+            log::warn!("Failed to create an innertube session: {}", error);
+            break;
+          }
+        }
+      }
+      app_settings.get_json_db().await.insert_json("sessions", "pool", &json!(http.sessions.sessions())).await;
+      tokio::select! {
+        _ = tokio::time::sleep(SESSION_KEEPER_INTERVAL) => {},
+        _ = http.sessions.replenish.notified() => {}
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::IpAddr;
+  use std::str::FromStr;
+  use crate::http::Egress;
+  use super::{Session, SessionPool};
+
+  fn session(id: &str, egress: Egress) -> Session {
+    Session { id: String::from(id), visitor_data: String::from(id), cookies: String::from(""), client_version: None, created_at: 0, requests: 0, egress }
+  }
+
+  #[test]
+  fn picks_sessions_created_from_the_same_egress() {
+    let pool = SessionPool::default();
+    let proxied = Egress { proxy: Some(0), source_address: None };
+    let from_source = Egress { proxy: None, source_address: Some(IpAddr::from_str("2001:db8::1").unwrap()) };
+    pool.add(session("direct", Egress::default()));
+    pool.add(session("proxied-1", proxied));
+    pool.add(session("proxied-2", proxied));
+    assert_eq!(pool.pick(&Egress::default()).unwrap().id, "direct");
+    let picked = (0..4).map(|_| pool.pick(&proxied).unwrap().id).collect::<Vec::<String>>();
+    assert!(picked.iter().all(|id| id.starts_with("proxied")));
+    assert!(picked.contains(&String::from("proxied-1")) && picked.contains(&String::from("proxied-2")));
+    assert!(pool.pick(&from_source).is_none());
+  }
+}
@@ -0,0 +1,284 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard, watch};
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use crate::innertube::InnertubeClient;
+use crate::http::HttpClient;
+use crate::player_js::PlayerJs;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PeerStats {
+  pub url: String,
+  pub healthy: bool,
+  // latency of the last health check
+  #[serde(rename = "latencyMs")]
+  pub latency_ms: Option<u64>,
+  // health checks and cache lookups
+  pub requests: u64,
+  pub failures: u64,
+  #[serde(rename = "failureRate")]
+  pub failure_rate: f64,
+  #[serde(rename = "lastChecked")]
+  pub last_checked: Option<i64>
+}
+
+impl PeerStats {
+  pub fn record(&mut self, is_failure: bool) {
+    self.requests += 1;
+    if is_failure {
+      self.failures += 1;
+    }
+    self.failure_rate = self.failures as f64 / self.requests as f64;
+  }
+}
+
+// the health of one innertube endpoint (`next`, `browse`, etc)
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct CircuitStats {
+  #[serde(rename = "consecutiveFailures")]
+  pub consecutive_failures: u32,
+  // when the circuit last opened (it's open for `--circuit-breaker-cooldown` seconds after this)
+  #[serde(rename = "openedAt")]
+  pub opened_at: Option<i64>
+}
+
+// async locks by key, which are only kept around while someone holds or is waiting on them
+#[derive(Default)]
+pub struct KeyedLocks {
+  locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>
+}
+
+pub struct KeyedLockGuard<'a> {
+  locks: &'a KeyedLocks,
+  key: String,
+  guard: Option<OwnedMutexGuard<()>>
+}
+
+impl KeyedLocks {
+  pub async fn lock(&self, key: &str) -> KeyedLockGuard<'_> {
+    let lock = self.locks.lock().unwrap().entry(String::from(key)).or_default().clone();
+    KeyedLockGuard {
+      locks: self,
+      key: String::from(key),
+      guard: Some(lock.lock_owned().await)
+    }
+  }
+}
+
+impl Drop for KeyedLockGuard<'_> {
+  fn drop(&mut self) {
+    let mut locks = self.locks.locks.lock().unwrap();
+    self.guard.take();
+    // waiters hold a reference too, so this means nobody else wants the lock
+    if locks.get(&self.key).map(|lock| Arc::strong_count(lock) == 1).unwrap_or(false) {
+      locks.remove(&self.key);
+    }
+  }
+}
+
+type SharedResult = Arc<dyn Any + Send + Sync>;
+
+// fetches in progress by key, so concurrent callers for the same key wait on the first one and all get a copy of its result (errors included)
+#[derive(Default)]
+pub struct InFlight {
+  fetches: Mutex<HashMap<String, watch::Receiver<Option<SharedResult>>>>
+}
+
+// removes the fetch once it's done, or once the caller running it goes away (then a waiter runs it instead)
+struct InFlightGuard<'a> {
+  in_flight: &'a InFlight,
+  key: String
+}
+
+impl Drop for InFlightGuard<'_> {
+  fn drop(&mut self) {
+    self.in_flight.fetches.lock().unwrap().remove(&self.key);
+  }
+}
+
+impl InFlight {
+  pub async fn coalesce<T: Clone + Send + Sync + 'static>(&self, key: &str, fetch: impl Future<Output = T>) -> T {
+    loop {
+      let fetch_or_wait = {
+        let mut fetches = self.fetches.lock().unwrap();
+        match fetches.get(key) {
+          Some(receiver) => Err(receiver.clone()),
+          None => {
+            let (sender, receiver) = watch::channel(None);
+            fetches.insert(String::from(key), receiver);
+            Ok(sender)
+          }
+        }
+      };
+      match fetch_or_wait {
+        Ok(sender) => {
+          let _guard = InFlightGuard { in_flight: self, key: String::from(key) };
+          let result = fetch.await;
+          sender.send_replace(Some(Arc::new(result.clone())));
+          return result;
+        },
+        Err(mut receiver) => loop {
+          let shared_result = receiver.borrow().clone();
+          if let Some(shared_result) = shared_result {
+            match shared_result.downcast_ref::<T>() {
+              Some(result) => return result.clone(),
+              // the same key was used for a different kind of fetch
+              None => return fetch.await
+            }
+          }
+          // the first caller went away before it finished, so try again (and probably run it this time)
+          if receiver.changed().await.is_err() {
+            break;
+          }
+        }
+      }
+    }
+  }
+}
+
+// runtime state shared by every worker (created once with the settings, so every clone points at the same state)
+#[derive(Default)]
+pub struct AppState {
+  pub peers: Mutex<Vec<PeerStats>>,
+  // every player client that has returned a playable response, in the order they first did
+  pub player_clients_used: Mutex<Vec<InnertubeClient>>,
+  pub circuits: Mutex<HashMap<String, CircuitStats>>,
+  // cache entries being fetched from upstream, so concurrent requests for one share its response instead of each going upstream
+  pub in_flight: InFlight,
+  pub http: HttpClient,
+  // swapped out whole by `player_js::refresh_player_js`, so a request never sees half of an update
+  player_js: Mutex<Option<Arc<PlayerJs>>>
+}
+
+impl AppState {
+  pub fn new(peers: &[String], http: HttpClient) -> AppState {
+    AppState {
+      // peers are assumed to be healthy until the first health check says otherwise
+      peers: Mutex::new(peers.iter().map(|url| PeerStats {
+        url: String::from(url),
+        healthy: true,
+        latency_ms: None,
+        requests: 0,
+        failures: 0,
+        failure_rate: 0.0,
+        last_checked: None
+      }).collect()),
+      player_clients_used: Mutex::new(vec![]),
+      circuits: Mutex::new(HashMap::new()),
+      in_flight: InFlight::default(),
+      http,
+      player_js: Mutex::new(None)
+    }
+  }
+  pub fn player_js(&self) -> Option<Arc<PlayerJs>> {
+    self.player_js.lock().unwrap().clone()
+  }
+  // returns the player.js this replaced
+  pub fn swap_player_js(&self, player_js: Arc<PlayerJs>) -> Option<Arc<PlayerJs>> {
+    self.player_js.lock().unwrap().replace(player_js)
+  }
+  pub fn record_player_client(&self, client: &InnertubeClient) {
+    let mut player_clients_used = self.player_clients_used.lock().unwrap();
+    if !player_clients_used.contains(client) {
+      player_clients_used.push(client.clone());
+    }
+  }
+  pub fn is_circuit_open(&self, endpoint: &str, cooldown: u64) -> bool {
+    match self.circuits.lock().unwrap().get(endpoint).and_then(|circuit| circuit.opened_at) {
+      Some(opened_at) => Utc::now().timestamp() - opened_at < cooldown as i64,
+      None => false
+    }
+  }
+  // once the cooldown is up, requests go upstream again, and the first failure reopens the circuit (the first success closes it)
+  pub fn record_upstream(&self, endpoint: &str, is_failure: bool, threshold: u32) {
+    let mut circuits = self.circuits.lock().unwrap();
+    let circuit = circuits.entry(String::from(endpoint)).or_default();
+    if is_failure {
+      circuit.consecutive_failures += 1;
+      if circuit.consecutive_failures >= threshold {
+        if circuit.opened_at.is_none() {
+          log::warn!("Opening the circuit for `{}` after {} failures", endpoint, circuit.consecutive_failures);
+        }
+        circuit.opened_at = Some(Utc::now().timestamp());
+      }
+    } else {
+      if circuit.opened_at.is_some() {
+        log::info!("Closing the circuit for `{}`", endpoint);
+      }
+      *circuit = CircuitStats::default();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU32, Ordering};
+  use std::time::Duration;
+  use futures_util::future::join_all;
+  use super::{InFlight, KeyedLocks};
+
+  #[actix_web::test]
+  async fn concurrent_callers_share_the_first_result_and_error() {
+    let in_flight = InFlight::default();
+    let fetches = AtomicU32::new(0);
+    let fetch = || async {
+      fetches.fetch_add(1, Ordering::SeqCst);
+      tokio::time::sleep(Duration::from_millis(50)).await;
+      Err::<u32, String>(String::from("upstream failed"))
+    };
+    let results = join_all((0..5).map(|_| in_flight.coalesce("next-jNQXAC9IVRw", fetch()))).await;
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    assert!(results.iter().all(|result| result == &Err(String::from("upstream failed"))));
+    // nothing is kept around once it's done
+    assert_eq!(in_flight.coalesce("next-jNQXAC9IVRw", async { Ok::<u32, String>(1) }).await, Ok(1));
+    assert!(in_flight.fetches.lock().unwrap().is_empty());
+  }
+
+  #[actix_web::test]
+  async fn waiters_run_the_fetch_when_the_first_caller_goes_away() {
+    let in_flight = InFlight::default();
+    let first = in_flight.coalesce("player-jNQXAC9IVRw", async {
+      tokio::time::sleep(Duration::from_secs(60)).await;
+      1
+    });
+    let second = in_flight.coalesce("player-jNQXAC9IVRw", async { 2 });
+    // the first caller is dropped (ex: the client disconnected) while the second one waits on it
+    let (first_result, second_result) = tokio::join!(
+      async {
+        tokio::select! {
+          _ = tokio::time::sleep(Duration::from_millis(50)) => None,
+          result = first => Some(result)
+        }
+      },
+      async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        tokio::time::timeout(Duration::from_secs(1), second).await
+      }
+    );
+    assert_eq!(first_result, None);
+    assert_eq!(second_result, Ok(2));
+  }
+
+  #[actix_web::test]
+  async fn keyed_locks_run_changes_to_the_same_key_one_at_a_time() {
+    let locks = KeyedLocks::default();
+    let history = std::sync::Mutex::new(vec![]);
+    // a read-modify-write with a wait in the middle, like `get_history` and `set_history`
+    let add = |video_id: &'static str| {
+      let (locks, history) = (&locks, &history);
+      async move {
+        let _lock = locks.lock("token").await;
+        let mut videos = history.lock().unwrap().clone();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        videos.push(video_id);
+        *history.lock().unwrap() = videos;
+      }
+    };
+    join_all(vec![add("jNQXAC9IVRw"), add("dQw4w9WgXcQ")]).await;
+    assert_eq!(history.lock().unwrap().len(), 2);
+    assert!(locks.locks.lock().unwrap().is_empty());
+  }
+}